@@ -0,0 +1,40 @@
+// Times typing in the middle of documents of growing size. With incremental
+// layout the time per keystroke should stay flat as the line count grows.
+//
+//     cargo bench --bench typing
+
+use std::time::{Duration, Instant};
+use text_model::document::Document;
+use text_model::font_loader::create_font_map;
+use text_model::movement::Movement;
+use text_model::{KeyCommand, TextModel};
+
+const KEYSTROKES: usize = 2000;
+
+fn make_document(lines: usize) -> Document {
+    let line = "the quick brown fox jumps over the lazy dog, again and again and again\n";
+    Document::from(line.repeat(lines).as_str())
+}
+
+fn time_typing(lines: usize) -> Duration {
+    let font_measure = create_font_map("./Lato-Regular.ttf");
+    let mut model = TextModel::with_document(make_document(lines), font_measure);
+    model.apply(KeyCommand::Size(300, 300));
+
+    for _ in 0..model.row_count() / 2 {
+        model.apply(KeyCommand::Move(Movement::Down));
+    }
+
+    let start = Instant::now();
+    for index in 0..KEYSTROKES {
+        let character = (b'a' + (index % 26) as u8) as char;
+        model.apply(KeyCommand::Value(character.to_string()));
+    }
+    start.elapsed() / KEYSTROKES as u32
+}
+
+fn main() {
+    for &lines in &[1_000, 10_000, 100_000] {
+        println!("{:>7} lines: {:?} per keystroke", lines, time_typing(lines));
+    }
+}
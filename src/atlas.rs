@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+
+const WIDTH: u32 = 512;
+const START_HEIGHT: u32 = 128;
+const MAX_HEIGHT: u32 = 2048;
+// Empty pixels around every glyph so linear filtering does not bleed in
+// the neighbours.
+const PADDING: u32 = 1;
+
+// Where a rasterized glyph sits in the atlas and how to place it.
+#[derive(Debug, Clone, Copy)]
+pub struct AtlasGlyph {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    // Offset of the bitmap's top left corner from the pen position, with y
+    // growing downwards.
+    pub left: i32,
+    pub top: i32,
+    last_used: u64,
+}
+
+#[derive(Debug)]
+struct Shelf {
+    y: u32,
+    height: u32,
+    next_x: u32,
+}
+
+// A glyph image in atlas texels.
+#[derive(Debug, Clone)]
+pub struct GlyphBitmap {
+    pub width: u32,
+    pub height: u32,
+    // Offset of the top left corner from the pen position, y down.
+    pub left: i32,
+    pub top: i32,
+    pub pixels: Vec<u8>,
+}
+
+// A glyph by the index of its face in the chain and its index in the face.
+pub type GlyphKey = (usize, u32);
+
+// Hinted, antialiased coverage from FreeType.
+pub fn rasterize(face: &ft::Face, index: u32) -> Option<GlyphBitmap> {
+    face.load_glyph(index, ft::face::LoadFlag::RENDER).ok()?;
+    let slot = face.glyph();
+    let bitmap = slot.bitmap();
+    let (width, height) = (bitmap.width() as u32, bitmap.rows() as u32);
+
+    // Empty glyphs like the space have no buffer at all.
+    let pixels: Vec<u8> = if width == 0 || height == 0 {
+        vec![]
+    } else {
+        let pitch = bitmap.pitch().unsigned_abs() as usize;
+        bitmap
+            .buffer()
+            .chunks(pitch)
+            .take(height as usize)
+            .flat_map(|row| row[..width as usize].iter().cloned())
+            .collect()
+    };
+
+    Some(GlyphBitmap {
+        width,
+        height,
+        left: slot.bitmap_left(),
+        top: -slot.bitmap_top(),
+        pixels,
+    })
+}
+
+// Glyph bitmaps packed on shelves in one 8-bit texture. The texture grows
+// downwards and, once at its largest, drops the glyphs that were not used in
+// the current frame.
+pub struct GlyphAtlas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    glyphs: HashMap<GlyphKey, AtlasGlyph>,
+    shelves: Vec<Shelf>,
+    frame: u64,
+    generation: u64,
+    dirty: bool,
+}
+
+impl Default for GlyphAtlas {
+    fn default() -> GlyphAtlas {
+        GlyphAtlas::new()
+    }
+}
+
+impl GlyphAtlas {
+    pub fn new() -> GlyphAtlas {
+        GlyphAtlas {
+            width: WIDTH,
+            height: START_HEIGHT,
+            pixels: vec![0; (WIDTH * START_HEIGHT) as usize],
+            glyphs: HashMap::new(),
+            shelves: vec![],
+            frame: 0,
+            generation: 0,
+            dirty: true,
+        }
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    // Makes the bitmap with `rasterize` on first use. `None` when that fails
+    // or the glyph does not fit even in an emptied atlas.
+    pub fn get<F>(&mut self, key: GlyphKey, rasterize: F) -> Option<AtlasGlyph>
+    where
+        F: FnOnce() -> Option<GlyphBitmap>,
+    {
+        if let Some(glyph) = self.glyphs.get_mut(&key) {
+            glyph.last_used = self.frame;
+            return Some(*glyph);
+        }
+
+        let bitmap = rasterize()?;
+        let (x, y) = self.allocate(bitmap.width, bitmap.height)?;
+
+        for (row, source) in bitmap
+            .pixels
+            .chunks(bitmap.width.max(1) as usize)
+            .enumerate()
+        {
+            let start = (y as usize + row) * self.width as usize + x as usize;
+            self.pixels[start..start + bitmap.width as usize].copy_from_slice(source);
+        }
+
+        let glyph = AtlasGlyph {
+            x,
+            y,
+            width: bitmap.width,
+            height: bitmap.height,
+            left: bitmap.left,
+            top: bitmap.top,
+            last_used: self.frame,
+        };
+        self.glyphs.insert(key, glyph);
+        self.dirty = true;
+        Some(glyph)
+    }
+
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        if width == 0 || height == 0 {
+            return Some((0, 0));
+        }
+
+        if let Some(place) = self.pack(width, height) {
+            return Some(place);
+        }
+
+        while self.height < MAX_HEIGHT {
+            self.grow();
+            if let Some(place) = self.pack(width, height) {
+                return Some(place);
+            }
+        }
+
+        self.evict();
+        self.pack(width, height)
+    }
+
+    // Puts the glyph on the lowest shelf it fits, or opens a new shelf.
+    fn pack(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let (width, height) = (width + PADDING, height + PADDING);
+
+        if width > self.width {
+            return None;
+        }
+
+        let atlas_width = self.width;
+        let shelf = self
+            .shelves
+            .iter_mut()
+            .filter(|shelf| shelf.height >= height && shelf.next_x + width <= atlas_width)
+            .min_by_key(|shelf| shelf.height);
+
+        if let Some(shelf) = shelf {
+            let x = shelf.next_x;
+            shelf.next_x += width;
+            return Some((x, shelf.y));
+        }
+
+        let y = self
+            .shelves
+            .last()
+            .map_or(0, |shelf| shelf.y + shelf.height);
+        if y + height > self.height {
+            return None;
+        }
+
+        self.shelves.push(Shelf {
+            y,
+            height,
+            next_x: width,
+        });
+        Some((0, y))
+    }
+
+    // Doubles the height. Rows are appended, so placed glyphs keep their
+    // coordinates.
+    fn grow(&mut self) {
+        self.height = (self.height * 2).min(MAX_HEIGHT);
+        self.pixels.resize((self.width * self.height) as usize, 0);
+        self.dirty = true;
+    }
+
+    // Repacks the glyphs used in this frame into an empty atlas.
+    fn evict(&mut self) {
+        let frame = self.frame;
+        if self.glyphs.values().all(|glyph| glyph.last_used == frame) {
+            // Everything is on screen, nothing can go.
+            return;
+        }
+
+        let old_pixels = std::mem::replace(
+            &mut self.pixels,
+            vec![0; (self.width * self.height) as usize],
+        );
+        let old_glyphs = std::mem::take(&mut self.glyphs);
+        self.shelves.clear();
+
+        let mut kept: Vec<(GlyphKey, AtlasGlyph)> = old_glyphs
+            .into_iter()
+            .filter(|(_, glyph)| glyph.last_used == frame)
+            .collect();
+        // Tall glyphs first packs the shelves tighter.
+        kept.sort_by_key(|(_, glyph)| std::cmp::Reverse(glyph.height));
+
+        for (key, mut glyph) in kept {
+            if glyph.width == 0 || glyph.height == 0 {
+                self.glyphs.insert(key, glyph);
+                continue;
+            }
+
+            let (x, y) = match self.pack(glyph.width, glyph.height) {
+                Some(place) => place,
+                None => continue,
+            };
+
+            for row in 0..glyph.height as usize {
+                let from = (glyph.y as usize + row) * self.width as usize + glyph.x as usize;
+                let to = (y as usize + row) * self.width as usize + x as usize;
+                self.pixels[to..to + glyph.width as usize]
+                    .copy_from_slice(&old_pixels[from..from + glyph.width as usize]);
+            }
+
+            glyph.x = x;
+            glyph.y = y;
+            self.glyphs.insert(key, glyph);
+        }
+
+        self.generation += 1;
+        self.dirty = true;
+    }
+
+    // Drops every glyph, keeping the texture size.
+    pub fn clear(&mut self) {
+        self.pixels.iter_mut().for_each(|pixel| *pixel = 0);
+        self.glyphs.clear();
+        self.shelves.clear();
+        self.generation += 1;
+        self.dirty = true;
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    // Changes whenever placed glyphs move, which makes texture coordinates
+    // taken before stale.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Whether the pixels changed since the last call.
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::replace(&mut self.dirty, false)
+    }
+}
@@ -0,0 +1,72 @@
+use std::ops::Range;
+use unicode_bidi::{
+    bidi_class, get_base_direction, BidiClass, Direction, Level, ParagraphBidiInfo,
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+// Whether the first strong char of the text runs right-to-left, `None` when
+// it only has neutral chars.
+pub fn paragraph_rtl(text: &str) -> Option<bool> {
+    match get_base_direction(text) {
+        Direction::Ltr => Some(false),
+        Direction::Rtl => Some(true),
+        Direction::Mixed => None,
+    }
+}
+
+// Chars that can put text of a left-to-right paragraph out of logical order.
+fn may_reorder(c: char) -> bool {
+    matches!(
+        bidi_class(c),
+        BidiClass::R
+            | BidiClass::AL
+            | BidiClass::AN
+            | BidiClass::RLE
+            | BidiClass::RLO
+            | BidiClass::RLI
+            | BidiClass::FSI
+    )
+}
+
+// Byte ranges of the runs of one direction in the text, in visual order from
+// the left, and whether each runs right-to-left. `rtl` is the direction of
+// the paragraph, which for a wrapped row is that of its whole line.
+pub fn visual_runs(text: &str, rtl: bool) -> Vec<(Range<usize>, bool)> {
+    if !rtl && !text.chars().any(may_reorder) {
+        return vec![(0..text.len(), false)];
+    }
+
+    let level = if rtl { Level::rtl() } else { Level::ltr() };
+    let info = ParagraphBidiInfo::new(text, Some(level));
+    let (levels, runs) = info.visual_runs(0..text.len());
+
+    runs.into_iter()
+        .map(|run| {
+            let rtl = levels[run.start].is_rtl();
+            (run, rtl)
+        })
+        .collect()
+}
+
+// Logical indices of the grapheme clusters of the text in visual order from
+// the left, with whether each is drawn right-to-left.
+pub fn visual_graphemes(text: &str, rtl: bool) -> Vec<(usize, bool)> {
+    let starts: Vec<usize> = text
+        .grapheme_indices(true)
+        .map(|(byte_index, _)| byte_index)
+        .collect();
+    let mut order = Vec::with_capacity(starts.len());
+
+    for (range, run_rtl) in visual_runs(text, rtl) {
+        let first = starts.partition_point(|&start| start < range.start);
+        let end = starts.partition_point(|&start| start < range.end);
+
+        if run_rtl {
+            order.extend((first..end).rev().map(|index| (index, true)));
+        } else {
+            order.extend((first..end).map(|index| (index, false)));
+        }
+    }
+
+    order
+}
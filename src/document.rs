@@ -0,0 +1,183 @@
+use ropey::Rope;
+use std::ops::Range;
+
+// Lines `line..line + removed` of the document before an edit were replaced
+// by `line..line + inserted` after it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineChange {
+    pub line: usize,
+    pub removed: usize,
+    pub inserted: usize,
+}
+
+// Rope backed storage for the text. Positions are char indices into the whole
+// document, lines are logical lines separated by '\n'.
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    rope: Rope,
+    // Lines touched since the last take_changes, in the order of the edits.
+    changes: Vec<LineChange>,
+}
+
+impl Document {
+    pub fn new() -> Document {
+        Document::default()
+    }
+
+    pub fn insert(&mut self, char_index: usize, text: &str) {
+        let line = self.rope.char_to_line(char_index);
+        let lines_before = self.rope.len_lines();
+
+        self.rope.insert(char_index, text);
+
+        self.changes.push(LineChange {
+            line,
+            removed: 1,
+            inserted: 1 + self.rope.len_lines() - lines_before,
+        });
+    }
+
+    pub fn remove(&mut self, range: Range<usize>) {
+        let line = self.rope.char_to_line(range.start);
+        let lines_before = self.rope.len_lines();
+
+        self.rope.remove(range);
+
+        self.changes.push(LineChange {
+            line,
+            removed: 1 + lines_before - self.rope.len_lines(),
+            inserted: 1,
+        });
+    }
+
+    pub fn take_changes(&mut self) -> Vec<LineChange> {
+        std::mem::take(&mut self.changes)
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.rope.len_chars()
+    }
+
+    pub fn len_lines(&self) -> usize {
+        self.rope.len_lines()
+    }
+
+    pub fn char_at(&self, char_index: usize) -> char {
+        self.rope.char(char_index)
+    }
+
+    pub fn char_to_byte(&self, char_index: usize) -> usize {
+        self.rope.char_to_byte(char_index)
+    }
+
+    pub fn byte_to_char(&self, byte_index: usize) -> usize {
+        self.rope.byte_to_char(byte_index)
+    }
+
+    pub fn char_to_line(&self, char_index: usize) -> usize {
+        self.rope.char_to_line(char_index)
+    }
+
+    pub fn line_to_char(&self, line_index: usize) -> usize {
+        self.rope.line_to_char(line_index)
+    }
+
+    // Number of chars in the line, not counting the line break.
+    pub fn line_len(&self, line_index: usize) -> usize {
+        let line = self.rope.line(line_index);
+        let len = line.len_chars();
+
+        if len > 0 && line.char(len - 1) == '\n' {
+            len - 1
+        } else {
+            len
+        }
+    }
+
+    // Text of the line without the line break.
+    pub fn line(&self, line_index: usize) -> String {
+        let start = self.line_to_char(line_index);
+        let end = start + self.line_len(line_index);
+        self.rope.slice(start..end).to_string()
+    }
+
+    pub fn slice(&self, range: Range<usize>) -> String {
+        self.rope.slice(range).to_string()
+    }
+
+    pub fn text(&self) -> String {
+        self.rope.to_string()
+    }
+
+    // Char index of the first match of `needle` at or after `from`. The rope
+    // is searched chunk by chunk; the end of the text before every chunk is
+    // kept to find matches across chunk borders.
+    pub fn find(&self, needle: &str, from: usize) -> Option<usize> {
+        if needle.is_empty() {
+            return None;
+        }
+
+        let mut offset = self.rope.char_to_byte(from);
+        let mut tail = String::new();
+
+        for chunk in self.rope.slice(from..).chunks() {
+            let mut prefix = (needle.len() - 1).min(chunk.len());
+            while !chunk.is_char_boundary(prefix) {
+                prefix += 1;
+            }
+
+            let window = tail.clone() + &chunk[..prefix];
+            let found = match window.find(needle) {
+                Some(index) if index < tail.len() => Some(offset - tail.len() + index),
+                _ => chunk.find(needle).map(|index| offset + index),
+            };
+            if let Some(byte_index) = found {
+                return Some(self.rope.byte_to_char(byte_index));
+            }
+
+            tail.push_str(chunk);
+            let mut start = tail.len().saturating_sub(needle.len() - 1);
+            while !tail.is_char_boundary(start) {
+                start += 1;
+            }
+            tail.drain(..start);
+            offset += chunk.len();
+        }
+
+        None
+    }
+}
+
+impl From<&str> for Document {
+    fn from(text: &str) -> Document {
+        Document {
+            rope: Rope::from_str(text),
+            changes: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_matches_across_chunks() {
+        // Long enough for the rope to split it into many chunks.
+        let text = "é-".repeat(5000) + "needle" + &"ü".repeat(5000) + "needle";
+        let document = Document::from(text.as_str());
+        let first = 10000;
+
+        assert_eq!(document.find("needle", 0), Some(first));
+        assert_eq!(document.find("needle", first), Some(first));
+        assert_eq!(document.find("needle", first + 1), Some(first + 6 + 5000));
+        assert_eq!(document.find("needle", first + 6 + 5001), None);
+        assert_eq!(document.find("", 0), None);
+
+        // Every split of the needle over a chunk border is found.
+        for start in (0..text.chars().count() - 8).step_by(97) {
+            let needle = document.slice(start..start + 8);
+            assert_eq!(document.find(&needle, start), Some(start));
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use crate::document::Document;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FileError {
+    Io(io::Error),
+    // The file has NUL bytes or is not valid UTF-8.
+    Binary,
+    // Save was asked for a document that was never given a path.
+    NoPath,
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileError::Io(error) => write!(f, "{}", error),
+            FileError::Binary => write!(f, "refusing to open a binary file"),
+            FileError::NoPath => write!(f, "the document has no file path"),
+        }
+    }
+}
+
+impl std::error::Error for FileError {}
+
+impl From<io::Error> for FileError {
+    fn from(error: io::Error) -> FileError {
+        FileError::Io(error)
+    }
+}
+
+// The most common line break in the text wins, ties go to LF.
+pub fn detect_line_ending(text: &str) -> LineEnding {
+    let crlf = text.matches("\r\n").count();
+    let lf = text.matches('\n').count() - crlf;
+
+    if crlf > lf {
+        LineEnding::CrLf
+    } else {
+        LineEnding::Lf
+    }
+}
+
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.contains(&0)
+}
+
+// Reads a text file into a document with LF line breaks, returning the line
+// ending style to write it back with.
+pub fn read_file(path: &Path) -> Result<(Document, LineEnding), FileError> {
+    let bytes = fs::read(path)?;
+
+    if is_binary(&bytes) {
+        return Err(FileError::Binary);
+    }
+
+    let text = String::from_utf8(bytes).map_err(|_| FileError::Binary)?;
+    let line_ending = detect_line_ending(&text);
+
+    let document = if text.contains("\r\n") {
+        Document::from(text.replace("\r\n", "\n").as_str())
+    } else {
+        Document::from(text.as_str())
+    };
+
+    Ok((document, line_ending))
+}
+
+pub fn write_file(
+    path: &Path,
+    document: &Document,
+    line_ending: LineEnding,
+) -> Result<(), FileError> {
+    let text = match line_ending {
+        LineEnding::Lf => document.text(),
+        LineEnding::CrLf => document.text().replace('\n', line_ending.as_str()),
+    };
+
+    fs::write(path, text)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // A file in the temporary directory that is removed again.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &[u8]) -> TempFile {
+            let path =
+                std::env::temp_dir().join(format!("text-model-{}-{}", std::process::id(), name));
+            fs::write(&path, contents).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn most_common_line_ending_wins() {
+        assert_eq!(detect_line_ending(""), LineEnding::Lf);
+        assert_eq!(detect_line_ending("a\r\nb\r\nc\n"), LineEnding::CrLf);
+        assert_eq!(detect_line_ending("a\r\nb\nc\n"), LineEnding::Lf);
+        assert_eq!(detect_line_ending("a\r\nb\n"), LineEnding::Lf);
+    }
+
+    #[test]
+    fn crlf_files_round_trip() {
+        let file = TempFile::new("crlf", b"one\r\ntwo\r\n");
+
+        let (document, line_ending) = read_file(&file.0).unwrap();
+        assert_eq!(document.text(), "one\ntwo\n");
+        assert_eq!(line_ending, LineEnding::CrLf);
+
+        write_file(&file.0, &document, line_ending).unwrap();
+        assert_eq!(fs::read(&file.0).unwrap(), b"one\r\ntwo\r\n");
+    }
+
+    #[test]
+    fn lf_files_round_trip() {
+        let file = TempFile::new("lf", "é\nü".as_bytes());
+
+        let (document, line_ending) = read_file(&file.0).unwrap();
+        assert_eq!(line_ending, LineEnding::Lf);
+
+        write_file(&file.0, &document, line_ending).unwrap();
+        assert_eq!(fs::read(&file.0).unwrap(), "é\nü".as_bytes());
+    }
+
+    #[test]
+    fn binary_files_are_refused() {
+        let nul = TempFile::new("nul", b"a\0b");
+        let latin1 = TempFile::new("latin1", b"caf\xe9");
+
+        assert!(matches!(read_file(&nul.0), Err(FileError::Binary)));
+        assert!(matches!(read_file(&latin1.0), Err(FileError::Binary)));
+    }
+}
@@ -1,58 +1,103 @@
-use num_traits::{clamp, clamp_max, clamp_min, sign};
+use crate::atlas::{rasterize, GlyphBitmap};
+use crate::bidi::paragraph_rtl;
+use crate::shaping::{shape, ShapedGlyph};
+use crate::tessellate::triangulate;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
+use ttf_parser::gpos::{PairAdjustment, PositioningSubtable};
+use ttf_parser::{GlyphId, Tag};
 
-fn draw_curve(
-    curve: ft::outline::Curve,
-    per_w: f32,
-    per_h: f32,
-    xmin: f32,
-    ymin: f32,
-    points: &mut Vec<(f32, f32)>,
-    curve_points: &mut Vec<(f32, f32)>,
-    full_points: &mut Vec<(f32, f32)>,
-) {
-    match curve {
-        ft::outline::Curve::Line(pt) => {
-            let diff_x = (xmin as f32 - pt.x as f32).abs();
-            let diff_y = (ymin as f32 - -pt.y as f32).abs();
-
-            points.push((diff_x * per_w, diff_y * per_h));
-
-            full_points.push((diff_x * per_w, diff_y * per_h));
-        }
-        ft::outline::Curve::Bezier2(pt1, pt2) => {
-            let diff_x_1 = (xmin as f32 - pt1.x as f32).abs();
-            let diff_y_1 = (ymin as f32 - -pt1.y as f32).abs();
+pub const DEFAULT_POINT_SIZE: f32 = 20.0;
+// What a content scale of 1 means on most desktops.
+pub const DEFAULT_DPI: u32 = 96;
+
+// Largest distance, in pixels, that a flattened curve may stray from the
+// real one.
+pub const DEFAULT_TOLERANCE: f32 = 0.1;
+
+// Spaces between tab stops.
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+const MAX_SHAPED_WORDS: usize = 8192;
 
-            let diff_x_2 = (xmin as f32 - pt2.x as f32).abs();
-            let diff_y_2 = (ymin as f32 - -pt2.y as f32).abs();
+// Face, direction and text of a shaped word.
+type WordKey = (usize, bool, String);
 
-            full_points.push((diff_x_1 * per_w, diff_y_1 * per_h));
+// Outline point in pixels from the pen position on the baseline, with y
+// growing downwards like the window.
+fn to_pixels(point: (f32, f32)) -> (f32, f32) {
+    (point.0 / 64.0, -point.1 / 64.0)
+}
+
+fn vector(point: ft::Vector) -> (f32, f32) {
+    (point.x as f32, point.y as f32)
+}
+
+// Point on the Bézier curve with the given control points at `t`, by
+// de Casteljau's algorithm.
+fn bezier_point(controls: &[(f32, f32)], t: f32) -> (f32, f32) {
+    let mut points = controls.to_vec();
+
+    while points.len() > 1 {
+        for i in 0..points.len() - 1 {
+            points[i] = (
+                points[i].0 + (points[i + 1].0 - points[i].0) * t,
+                points[i].1 + (points[i + 1].1 - points[i].1) * t,
+            );
         }
-        _ => (),
+        points.pop();
     }
+    points[0]
 }
 
-fn calculate_aspect_ratio_fit(
-    srcWidth: f32,
-    srcHeight: f32,
-    maxWidth: f32,
-    maxHeight: f32,
-) -> RatioSize {
-    let ratio = clamp_min(maxWidth / srcWidth, maxHeight / srcHeight);
-
-    return RatioSize {
-        width: srcWidth * ratio,
-        height: srcHeight * ratio,
-        ratio,
-    };
+// Points along the curve after its start, ending on its end point. The
+// count of segments comes from Wang's formula, which bounds the distance of
+// a uniformly split curve from its chords by `tolerance`.
+fn flatten(controls: &[(f32, f32)], tolerance: f32) -> Vec<(f32, f32)> {
+    let degree = controls.len() - 1;
+
+    let bend = (0..degree.saturating_sub(1))
+        .map(|i| {
+            let (a, b, c) = (controls[i], controls[i + 1], controls[i + 2]);
+            let (x, y) = (a.0 - 2.0 * b.0 + c.0, a.1 - 2.0 * b.1 + c.1);
+            (x * x + y * y).sqrt()
+        })
+        .fold(0.0, f32::max);
+
+    let factor = (degree * degree.saturating_sub(1)) as f32 / 8.0;
+    let segments = ((factor * bend / tolerance).sqrt().ceil() as usize).max(1);
+
+    (1..=segments)
+        .map(|i| bezier_point(controls, i as f32 / segments as f32))
+        .collect()
 }
 
-#[derive(Debug)]
-struct RatioSize {
-    width: f32,
-    height: f32,
-    ratio: f32,
+// Appends the points of the curve starting at `from` to the contour and
+// returns its end point.
+fn draw_curve(
+    curve: ft::outline::Curve,
+    from: (f32, f32),
+    tolerance: f32,
+    contour: &mut Vec<(f32, f32)>,
+) -> (f32, f32) {
+    let flattened = match curve {
+        ft::outline::Curve::Line(pt) => vec![vector(pt)],
+        ft::outline::Curve::Bezier2(pt1, pt2) => {
+            flatten(&[from, vector(pt1), vector(pt2)], tolerance * 64.0)
+        }
+        ft::outline::Curve::Bezier3(pt1, pt2, pt3) => flatten(
+            &[from, vector(pt1), vector(pt2), vector(pt3)],
+            tolerance * 64.0,
+        ),
+    };
+
+    contour.extend(flattened.iter().map(|&point| to_pixels(point)));
+    *flattened.last().unwrap()
 }
 
 #[derive(Debug)]
@@ -62,81 +107,395 @@ pub struct FontSize {
     pub advance: i64,
     pub ascender: i16,
     pub descender: i16,
-    pub points: Vec<(f32, f32)>,
+    // Outline in pixels from the pen position, one polygon per contour.
+    pub contours: Vec<Vec<(f32, f32)>>,
+    // Three points per triangle, in the same space as `contours`.
+    pub triangles: Vec<(f32, f32)>,
+    // Index of the face in the fallback chain the glyph was loaded from.
+    pub face: usize,
+    // Index of the glyph in that face.
+    pub index: u32,
 }
 
-pub fn create_font_map(font: &str) -> HashMap<char, FontSize> {
-    // Freetype get measurements
-    let library = ft::Library::init().unwrap();
-    let face = library.new_face(font, 0).unwrap();
-    face.set_char_size(40 * 64, 0, 50, 0).unwrap();
+// Metrics and outlines of glyphs, loaded the first time a char is asked for.
+// Each char comes from the first face in the chain that has it; chars no
+// face has get the `.notdef` glyph of the first face, so every char has a
+// size.
+pub struct GlyphCache {
+    faces: Vec<ft::Face>,
+    // The font files the faces were made from, for the tables FreeType does
+    // not read.
+    fonts: Vec<Rc<Vec<u8>>>,
+    tolerance: f32,
+    point_size: f32,
+    dpi: u32,
+    tab_width: usize,
+    ascender: i16,
+    descender: i16,
+    notdef: Arc<FontSize>,
+    glyphs: RefCell<HashMap<char, Arc<FontSize>>>,
+    // Glyphs by face and glyph index, as shaping gives them.
+    indexed: RefCell<HashMap<(usize, u32), Arc<FontSize>>>,
+    kerning: RefCell<HashMap<(char, char), i64>>,
+    // Shaped words by face, direction and text, with clusters from the start
+    // of the word.
+    words: RefCell<HashMap<WordKey, Arc<Vec<ShapedGlyph>>>>,
+}
 
-    let string = String::from("!\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~");
+// The faces are only ever used by the thread that owns the cache, and
+// FreeType allows moving a face together with its library to another thread.
+// No face leaves the cache, so the font data and the library are shared only
+// between the cache and its own faces.
+unsafe impl Send for GlyphCache {}
 
-    let mut font_measure: HashMap<char, FontSize> = HashMap::new();
+impl GlyphCache {
+    pub fn get(&self, c: char) -> Arc<FontSize> {
+        if let Some(size) = self.glyphs.borrow().get(&c) {
+            return size.clone();
+        }
 
-    for c in string.chars() {
-        face.load_char(c as usize, ft::face::LoadFlag::DEFAULT)
-            .unwrap();
-        let get_metrics = face.glyph().metrics();
-        let get_advance = face.glyph().advance();
+        // Tabs take the space glyph and are widened to the next tab stop.
+        let code = if c == '\t' { ' ' } else { c };
 
-        let glyph = face.glyph();
-        let metrics = glyph.metrics();
-        let xmin = metrics.horiBearingX - 5;
-        let width = metrics.width + 10;
-        let ymin = -metrics.horiBearingY - 5;
-        let height = metrics.height + 10;
-        let outline = glyph.outline().unwrap();
+        let found = self.faces.iter().enumerate().find_map(|(face, ft_face)| {
+            match ft_face.get_char_index(code as usize) {
+                0 => None,
+                index => Some((face, index)),
+            }
+        });
 
-        let z_width = metrics.width >> 6;
-        let z_height = metrics.height >> 6;
+        let size = match found {
+            Some((face, index)) => self.glyph(face, index),
+            None => self.notdef.clone(),
+        };
 
-        let fit = calculate_aspect_ratio_fit(width as f32, height as f32, 200.0, 200.0); //z_*
+        self.glyphs.borrow_mut().insert(c, size.clone());
+        size
+    }
 
-        let per_w = fit.width / width as f32;
-        let per_h = fit.height / height as f32;
+    // The glyph with the index in the face of the chain.
+    pub fn glyph(&self, face: usize, index: u32) -> Arc<FontSize> {
+        if let Some(size) = self.indexed.borrow().get(&(face, index)) {
+            return size.clone();
+        }
 
-        let mut points: Vec<(f32, f32)> = vec![];
-        let mut full_points: Vec<(f32, f32)> = vec![];
-        let mut curve_point: Vec<(f32, f32)> = vec![];
+        let size = Arc::new(load_glyph(&self.faces, face, index, self.tolerance));
+        self.indexed
+            .borrow_mut()
+            .insert((face, index), size.clone());
+        size
+    }
 
-        for contour in outline.contours_iter() {
-            let start = contour.start();
+    pub fn advance(&self, c: char) -> i64 {
+        self.get(c).advance
+    }
+
+    // Adjustment of the pen between two chars drawn next to each other, from
+    // the GPOS pair positioning of the font or else its `kern` table. Chars
+    // from different faces are never kerned.
+    pub fn kerning(&self, left: char, right: char) -> i64 {
+        if let Some(kerning) = self.kerning.borrow().get(&(left, right)) {
+            return *kerning;
+        }
 
-            let diff_x = (xmin as f32 - start.x as f32).abs();
-            let diff_y = (ymin as f32 - -start.y as f32).abs();
+        let (left_size, right_size) = (self.get(left), self.get(right));
 
-            points.push((diff_x * per_w, diff_y * per_h));
+        let kerning = if left_size.face != right_size.face {
+            0
+        } else {
+            let face = &self.faces[left_size.face];
+            let gpos = gpos_kerning(
+                &self.fonts[left_size.face],
+                left_size.index,
+                right_size.index,
+            );
 
-            full_points.push((diff_x * per_w, diff_y * per_h));
+            match gpos {
+                Some(units) => self.units_to_pixels(left_size.face, units as i32),
+                None if face.has_kerning() => face
+                    .get_kerning(
+                        left_size.index,
+                        right_size.index,
+                        ft::face::KerningMode::KerningDefault,
+                    )
+                    .map_or(0, |vector| vector.x >> 6),
+                None => 0,
+            }
+        };
+
+        self.kerning.borrow_mut().insert((left, right), kerning);
+        kerning
+    }
+
+    // Width of a run of text as shaped, with kerning, ligatures and marks.
+    pub fn measure(&self, text: &str) -> i64 {
+        let rtl = paragraph_rtl(text).unwrap_or(false);
+        shape(text, rtl, self)
+            .iter()
+            .map(|glyph| glyph.advance)
+            .sum()
+    }
+
+    // The glyphs of a word shaped with the face in the direction, made by
+    // `shape` the first time. Words are dropped all at once when there are
+    // too many of them.
+    pub fn shaped_word<F>(
+        &self,
+        face: usize,
+        rtl: bool,
+        word: &str,
+        shape: F,
+    ) -> Arc<Vec<ShapedGlyph>>
+    where
+        F: FnOnce() -> Vec<ShapedGlyph>,
+    {
+        let key = (face, rtl, String::from(word));
+        if let Some(glyphs) = self.words.borrow().get(&key) {
+            return glyphs.clone();
+        }
+
+        let glyphs = Arc::new(shape());
+        let mut words = self.words.borrow_mut();
+        if words.len() >= MAX_SHAPED_WORDS {
+            words.clear();
+        }
+        words.insert(key, glyphs.clone());
+        glyphs
+    }
+
+    // Design units of the face in whole pixels at the current size.
+    pub fn units_to_pixels(&self, face: usize, units: i32) -> i64 {
+        let x_scale = self.faces[face]
+            .size_metrics()
+            .map_or(0, |metrics| metrics.x_scale);
+        (units as i64 * x_scale / 65536 + 32) >> 6
+    }
+
+    // Font-wide metrics come from the first face, so rows keep one height and
+    // baseline whatever faces their glyphs come from.
+    pub fn ascender(&self) -> i16 {
+        self.ascender
+    }
+
+    pub fn descender(&self) -> i16 {
+        self.descender
+    }
+
+    // Scales every face and drops the loaded glyphs, which are measured at
+    // the new size when they are next asked for.
+    pub fn set_size(&mut self, point_size: f32, dpi: u32) {
+        for (index, face) in self.faces.iter().enumerate() {
+            let scaled = face.set_char_size((point_size * 64.0) as isize, 0, dpi, dpi);
+            if index == 0 {
+                scaled.unwrap();
+            }
+        }
+
+        let size_metrics = self.faces[0].size_metrics().unwrap();
+        self.point_size = point_size;
+        self.dpi = dpi;
+        self.ascender = (size_metrics.ascender >> 6) as i16;
+        self.descender = (size_metrics.descender >> 6) as i16;
+        self.notdef = Arc::new(load_glyph(&self.faces, 0, 0, self.tolerance));
+        self.glyphs.borrow_mut().clear();
+        self.indexed.borrow_mut().clear();
+        self.kerning.borrow_mut().clear();
+        self.words.borrow_mut().clear();
+    }
+
+    pub fn tab_width(&self) -> usize {
+        self.tab_width
+    }
+
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        self.tab_width = tab_width.max(1);
+    }
+
+    // Distance in pixels between tab stops, a number of space advances.
+    pub fn tab_stop(&self) -> i64 {
+        (self.tab_width as i64 * self.advance(' ')).max(1)
+    }
+
+    pub fn point_size(&self) -> f32 {
+        self.point_size
+    }
+
+    pub fn dpi(&self) -> u32 {
+        self.dpi
+    }
+
+    // Coverage of a glyph of the face `FontSize::face` refers to, at the
+    // current size.
+    pub fn rasterize(&self, face: usize, index: u32) -> Option<GlyphBitmap> {
+        rasterize(&self.faces[face], index)
+    }
+
+    // The font file of that face.
+    pub fn font(&self, index: usize) -> &[u8] {
+        &self.fonts[index]
+    }
+}
+
+impl fmt::Debug for GlyphCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GlyphCache")
+            .field("loaded", &self.glyphs.borrow().len())
+            .finish()
+    }
+}
+
+// Sum of the advance adjustments for the pair in the lookups of the GPOS
+// `kern` feature, `None` when no lookup has the pair.
+fn gpos_kerning(font: &[u8], left: u32, right: u32) -> Option<i16> {
+    let face = ttf_parser::Face::parse(font, 0).ok()?;
+    let gpos = face.tables().gpos?;
+    let (left, right) = (GlyphId(left as u16), GlyphId(right as u16));
+
+    // Every script has its own `kern` feature, usually sharing the lookups.
+    let mut lookups: Vec<u16> = gpos
+        .features
+        .into_iter()
+        .filter(|feature| feature.tag == Tag::from_bytes(b"kern"))
+        .flat_map(|feature| feature.lookup_indices)
+        .collect();
+    lookups.sort_unstable();
+    lookups.dedup();
+
+    let mut kerning = None;
+
+    for lookup in lookups
+        .into_iter()
+        .filter_map(|index| gpos.lookups.get(index))
+    {
+        // Only the first subtable that has the pair applies.
+        let adjustment = lookup
+            .subtables
+            .into_iter::<PositioningSubtable>()
+            .find_map(|subtable| match subtable {
+                PositioningSubtable::Pair(PairAdjustment::Format1 { coverage, sets }) => {
+                    let set = sets.get(coverage.get(left)?)?;
+                    set.get(right).map(|(first, _)| first.x_advance)
+                }
+                PositioningSubtable::Pair(PairAdjustment::Format2 {
+                    coverage,
+                    classes,
+                    matrix,
+                }) => {
+                    coverage.get(left)?;
+                    let pair = (classes.0.get(left), classes.1.get(right));
+                    matrix.get(pair).map(|(first, _)| first.x_advance)
+                }
+                _ => None,
+            });
+
+        if let Some(adjustment) = adjustment {
+            kerning = Some(kerning.unwrap_or(0) + adjustment);
+        }
+    }
+
+    kerning
+}
+
+fn load_glyph(faces: &[ft::Face], face_index: usize, index: u32, tolerance: f32) -> FontSize {
+    let face = &faces[face_index];
+    face.load_glyph(index, ft::face::LoadFlag::DEFAULT).unwrap();
+    let size_metrics = face.size_metrics().unwrap();
+
+    let glyph = face.glyph();
+    let metrics = glyph.metrics();
+
+    let mut contours: Vec<Vec<(f32, f32)>> = vec![];
+
+    // Bitmap glyphs have no outline to read.
+    if let Some(outline) = glyph.outline() {
+        for contour in outline.contours_iter() {
+            let start = contour.start();
+            let mut pixels = vec![to_pixels(vector(*start))];
+            let mut from = vector(*start);
 
             for curve in contour {
-                draw_curve(
-                    curve,
-                    per_w,
-                    per_h,
-                    xmin as f32,
-                    ymin as f32,
-                    &mut points,
-                    &mut curve_point,
-                    &mut full_points,
-                );
+                from = draw_curve(curve, from, tolerance, &mut pixels);
             }
+
+            contours.push(pixels);
         }
+    }
 
-        font_measure.insert(
-            c,
-            FontSize {
-                width: get_metrics.width >> 6,
-                height: get_metrics.height >> 6,
-                advance: get_advance.x >> 6,
-                // --
-                ascender: face.ascender(),
-                descender: face.descender(),
-                points: full_points,
-            },
-        );
-    }
-    font_measure
+    let triangles = triangulate(&contours);
+
+    FontSize {
+        width: metrics.width >> 6,
+        height: metrics.height >> 6,
+        advance: glyph.advance().x >> 6,
+        ascender: (size_metrics.ascender >> 6) as i16,
+        descender: (size_metrics.descender >> 6) as i16,
+        contours,
+        triangles,
+        face: face_index,
+        index,
+    }
+}
+
+pub fn create_font_map(font: &str) -> GlyphCache {
+    create_font_map_with_tolerance(font, DEFAULT_TOLERANCE)
+}
+
+// `tolerance` is how far, in pixels, the straight segments that replace the
+// outline curves may be from them.
+pub fn create_font_map_with_tolerance(font: &str, tolerance: f32) -> GlyphCache {
+    create_font_chain::<&str>(font, &[], DEFAULT_POINT_SIZE, DEFAULT_DPI, tolerance)
+}
+
+// A cache over `font` followed by the `fallbacks`, tried in order for chars
+// the fonts before them lack. Fallbacks that cannot be opened or scaled, like
+// bitmap-only emoji fonts, are left out of the chain.
+pub fn create_font_chain<P: AsRef<OsStr>>(
+    font: &str,
+    fallbacks: &[P],
+    point_size: f32,
+    dpi: u32,
+    tolerance: f32,
+) -> GlyphCache {
+    let library = ft::Library::init().unwrap();
+    let char_size = (point_size * 64.0) as isize;
+
+    let data = Rc::new(std::fs::read(font).unwrap());
+    let face = library.new_memory_face(data.clone(), 0).unwrap();
+    face.set_char_size(char_size, 0, dpi, dpi).unwrap();
+
+    let size_metrics = face.size_metrics().unwrap();
+
+    let mut fonts = vec![data];
+    let mut faces = vec![face];
+    for fallback in fallbacks {
+        let loaded = std::fs::read(Path::new(fallback)).ok().and_then(|data| {
+            let data = Rc::new(data);
+            let face = library.new_memory_face(data.clone(), 0).ok()?;
+            face.set_char_size(char_size, 0, dpi, dpi).ok()?;
+            Some((data, face))
+        });
+
+        if let Some((data, face)) = loaded {
+            fonts.push(data);
+            faces.push(face);
+        }
+    }
+
+    let notdef = Arc::new(load_glyph(&faces, 0, 0, tolerance));
+
+    GlyphCache {
+        faces,
+        fonts,
+        tolerance,
+        point_size,
+        dpi,
+        tab_width: DEFAULT_TAB_WIDTH,
+        ascender: (size_metrics.ascender >> 6) as i16,
+        descender: (size_metrics.descender >> 6) as i16,
+        notdef,
+        glyphs: RefCell::new(HashMap::new()),
+        indexed: RefCell::new(HashMap::new()),
+        kerning: RefCell::new(HashMap::new()),
+        words: RefCell::new(HashMap::new()),
+    }
 }
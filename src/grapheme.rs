@@ -0,0 +1,80 @@
+use crate::document::Document;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Conversions between the three ways of indexing into a string: extended
+// grapheme clusters (what the user sees as one character), chars (what the
+// document uses for positions) and bytes (what `str` slicing needs).
+
+pub fn char_to_byte(text: &str, char_index: usize) -> usize {
+    text.char_indices()
+        .nth(char_index)
+        .map(|(byte_index, _)| byte_index)
+        .unwrap_or_else(|| text.len())
+}
+
+pub fn grapheme_to_char(text: &str, grapheme_index: usize) -> usize {
+    text.graphemes(true)
+        .take(grapheme_index)
+        .map(|grapheme| grapheme.chars().count())
+        .sum()
+}
+
+// A char index inside a cluster maps to the cluster it belongs to.
+pub fn char_to_grapheme(text: &str, char_index: usize) -> usize {
+    let byte_index = char_to_byte(text, char_index);
+
+    text.grapheme_indices(true)
+        .take_while(|(start, _)| *start < byte_index)
+        .count()
+}
+
+pub fn grapheme_count(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+// Document position of the grapheme boundary before `position`. A line break
+// counts as its own cluster.
+pub fn prev_grapheme_boundary(document: &Document, position: usize) -> usize {
+    if position == 0 {
+        return 0;
+    }
+
+    let line_index = document.char_to_line(position);
+    let line_start = document.line_to_char(line_index);
+
+    if position == line_start {
+        return position - 1;
+    }
+
+    let line = document.line(line_index);
+    let byte_index = char_to_byte(&line, position - line_start);
+
+    let start = line[..byte_index]
+        .grapheme_indices(true)
+        .next_back()
+        .map(|(start, _)| line[..start].chars().count())
+        .unwrap_or(0);
+
+    line_start + start
+}
+
+pub fn next_grapheme_boundary(document: &Document, position: usize) -> usize {
+    let line_index = document.char_to_line(position);
+    let line_start = document.line_to_char(line_index);
+    let column = position - line_start;
+
+    if column >= document.line_len(line_index) {
+        return (position + 1).min(document.len_chars());
+    }
+
+    let line = document.line(line_index);
+    let byte_index = char_to_byte(&line, column);
+
+    let len = line[byte_index..]
+        .graphemes(true)
+        .next()
+        .map(|grapheme| grapheme.chars().count())
+        .unwrap_or(1);
+
+    position + len
+}
@@ -0,0 +1,292 @@
+use crate::document::Document;
+use crate::selection::Selection;
+
+#[derive(Debug, Clone)]
+pub enum Edit {
+    Insert { position: usize, text: String },
+    Remove { position: usize, text: String },
+}
+
+impl Edit {
+    // Where a position ends up once this edit is applied. `after` decides
+    // whether a position right at an insertion moves past the inserted text.
+    pub fn map_position(&self, position: usize, after: bool) -> usize {
+        match self {
+            Edit::Insert { position: at, text } => {
+                if position > *at || (after && position == *at) {
+                    position + text.chars().count()
+                } else {
+                    position
+                }
+            }
+            Edit::Remove { position: at, text } => {
+                let len = text.chars().count();
+
+                if position >= at + len {
+                    position - len
+                } else if position > *at {
+                    *at
+                } else {
+                    position
+                }
+            }
+        }
+    }
+
+    pub fn map_selection(&self, selection: &Selection, after: bool) -> Selection {
+        Selection {
+            anchor: self.map_position(selection.anchor, after),
+            head: self.map_position(selection.head, after),
+        }
+    }
+
+    fn invert(&self) -> Edit {
+        match self {
+            Edit::Insert { position, text } => Edit::Remove {
+                position: *position,
+                text: text.clone(),
+            },
+            Edit::Remove { position, text } => Edit::Insert {
+                position: *position,
+                text: text.clone(),
+            },
+        }
+    }
+}
+
+pub fn apply_edit(document: &mut Document, edit: &Edit) {
+    match edit {
+        Edit::Insert { position, text } => document.insert(*position, text),
+        Edit::Remove { position, text } => {
+            document.remove(*position..*position + text.chars().count())
+        }
+    }
+}
+
+// Consecutive edits of the same kind are merged into one undo step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EditKind {
+    Typing,
+    Deleting,
+    Other,
+}
+
+#[derive(Debug)]
+struct Transaction {
+    id: usize,
+    edits: Vec<Edit>,
+    kind: EditKind,
+    selections_before: Vec<Selection>,
+    selections_after: Vec<Selection>,
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    undo_stack: Vec<Transaction>,
+    redo_stack: Vec<Transaction>,
+    // Set when something other than an edit happened (e.g. cursor movement),
+    // so the next edit starts a new transaction.
+    sealed: bool,
+    next_id: usize,
+}
+
+impl History {
+    pub fn new() -> History {
+        History::default()
+    }
+
+    pub fn record(
+        &mut self,
+        mut edits: Vec<Edit>,
+        kind: EditKind,
+        selections_before: Vec<Selection>,
+        selections_after: Vec<Selection>,
+    ) {
+        if edits.is_empty() {
+            return;
+        }
+
+        self.redo_stack.clear();
+
+        match self.undo_stack.last_mut() {
+            Some(last)
+                if !self.sealed
+                    && kind != EditKind::Other
+                    && last.kind == kind
+                    && last.selections_after == selections_before =>
+            {
+                last.edits.append(&mut edits);
+                last.selections_after = selections_after;
+            }
+            _ => {
+                self.next_id += 1;
+                self.undo_stack.push(Transaction {
+                    id: self.next_id,
+                    edits,
+                    kind,
+                    selections_before,
+                    selections_after,
+                });
+            }
+        }
+
+        self.sealed = false;
+    }
+
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    // Identifies the current state of the document, 0 for the original text.
+    // Compare against a saved value to tell whether there are unsaved changes.
+    pub fn revision(&self) -> usize {
+        self.undo_stack
+            .last()
+            .map_or(0, |transaction| transaction.id)
+    }
+
+    // Reverts the last transaction and returns the selections from before it.
+    pub fn undo(&mut self, document: &mut Document) -> Option<Vec<Selection>> {
+        let transaction = self.undo_stack.pop()?;
+
+        for edit in transaction.edits.iter().rev() {
+            apply_edit(document, &edit.invert());
+        }
+
+        let selections = transaction.selections_before.clone();
+        self.redo_stack.push(transaction);
+        self.sealed = true;
+        Some(selections)
+    }
+
+    pub fn redo(&mut self, document: &mut Document) -> Option<Vec<Selection>> {
+        let transaction = self.redo_stack.pop()?;
+
+        for edit in transaction.edits.iter() {
+            apply_edit(document, edit);
+        }
+
+        let selections = transaction.selections_after.clone();
+        self.undo_stack.push(transaction);
+        self.sealed = true;
+        Some(selections)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(history: &mut History, document: &mut Document, position: usize, text: &str) {
+        let edit = Edit::Insert {
+            position,
+            text: String::from(text),
+        };
+        apply_edit(document, &edit);
+
+        let after = position + text.chars().count();
+        history.record(
+            vec![edit],
+            EditKind::Typing,
+            vec![Selection::point(position)],
+            vec![Selection::point(after)],
+        );
+    }
+
+    #[test]
+    fn typing_is_undone_as_one_step() {
+        let mut document = Document::from("");
+        let mut history = History::new();
+
+        insert(&mut history, &mut document, 0, "a");
+        insert(&mut history, &mut document, 1, "b");
+        insert(&mut history, &mut document, 2, "c");
+        assert_eq!(document.text(), "abc");
+
+        assert_eq!(history.undo(&mut document), Some(vec![Selection::point(0)]));
+        assert_eq!(document.text(), "");
+        assert_eq!(history.undo(&mut document), None);
+
+        assert_eq!(history.redo(&mut document), Some(vec![Selection::point(3)]));
+        assert_eq!(document.text(), "abc");
+    }
+
+    #[test]
+    fn sealing_starts_a_new_step() {
+        let mut document = Document::from("");
+        let mut history = History::new();
+
+        insert(&mut history, &mut document, 0, "a");
+        history.seal();
+        insert(&mut history, &mut document, 1, "b");
+
+        history.undo(&mut document);
+        assert_eq!(document.text(), "a");
+        history.undo(&mut document);
+        assert_eq!(document.text(), "");
+    }
+
+    #[test]
+    fn other_edits_are_never_merged() {
+        let mut document = Document::from("");
+        let mut history = History::new();
+
+        for position in 0..2 {
+            let edit = Edit::Insert {
+                position,
+                text: String::from("\n"),
+            };
+            apply_edit(&mut document, &edit);
+            history.record(
+                vec![edit],
+                EditKind::Other,
+                vec![Selection::point(position)],
+                vec![Selection::point(position + 1)],
+            );
+        }
+
+        history.undo(&mut document);
+        assert_eq!(document.text(), "\n");
+    }
+
+    #[test]
+    fn a_new_edit_clears_redo() {
+        let mut document = Document::from("");
+        let mut history = History::new();
+
+        insert(&mut history, &mut document, 0, "a");
+        history.undo(&mut document);
+        insert(&mut history, &mut document, 0, "b");
+
+        assert_eq!(history.redo(&mut document), None);
+        assert_eq!(document.text(), "b");
+    }
+
+    #[test]
+    fn revision_follows_undo_and_redo() {
+        let mut document = Document::from("");
+        let mut history = History::new();
+        assert_eq!(history.revision(), 0);
+
+        insert(&mut history, &mut document, 0, "a");
+        let saved = history.revision();
+        assert_ne!(saved, 0);
+
+        history.undo(&mut document);
+        assert_eq!(history.revision(), 0);
+        history.redo(&mut document);
+        assert_eq!(history.revision(), saved);
+    }
+
+    #[test]
+    fn removals_map_positions_back() {
+        let edit = Edit::Remove {
+            position: 2,
+            text: String::from("cd"),
+        };
+
+        assert_eq!(edit.map_position(1, false), 1);
+        assert_eq!(edit.map_position(3, false), 2);
+        assert_eq!(edit.map_position(5, false), 3);
+    }
+}
@@ -0,0 +1,551 @@
+use crate::bidi::{paragraph_rtl, visual_graphemes};
+use crate::document::{Document, LineChange};
+use crate::font_loader::GlyphCache;
+use crate::grapheme::{char_to_grapheme, grapheme_count, grapheme_to_char};
+use crate::shaping::{grapheme_widths, shape};
+use crate::{BorderSize, Buf, Cursor};
+use std::ops::Range;
+use unicode_linebreak::linebreaks;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Left and right pixel edges of every grapheme cluster of the row as drawn,
+// in logical order, with whether it runs right-to-left.
+pub fn grapheme_edges(row: &Buf, get_sizes: &GlyphCache) -> Vec<(i64, i64, bool)> {
+    let widths = grapheme_widths(&row.text, &shape(&row.text, row.rtl, get_sizes));
+    let mut edges = vec![(0, 0, false); widths.len()];
+    let mut pen = 0;
+
+    for (index, rtl) in visual_graphemes(&row.text, row.rtl) {
+        edges[index] = (pen, pen + widths[index], rtl);
+        pen += widths[index];
+    }
+
+    edges
+}
+
+// Pixel offsets of the caret at every grapheme column of the row. The caret
+// sits at the leading edge of the cluster after it; where the text changes
+// direction the trailing edge of the cluster before is elsewhere, and a
+// second caret is drawn there.
+pub fn row_carets(row: &Buf, get_sizes: &GlyphCache) -> Vec<(i64, Option<i64>)> {
+    let edges = grapheme_edges(row, get_sizes);
+    let leading = |&(left, right, rtl): &(i64, i64, bool)| if rtl { right } else { left };
+    let trailing = |&(left, right, rtl): &(i64, i64, bool)| if rtl { left } else { right };
+
+    (0..=edges.len())
+        .map(|x| {
+            let before = x.checked_sub(1).map(|index| trailing(&edges[index]));
+            let after = edges.get(x).map(leading);
+
+            match (after, before) {
+                (Some(after), Some(before)) if after != before => (after, Some(before)),
+                (Some(after), _) => (after, None),
+                (None, before) => (before.unwrap_or(0), None),
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    // Every logical line is one row.
+    None,
+    // Break at the first cluster that does not fit.
+    Char,
+    // Break at UAX #14 line break opportunities, falling back to Char for
+    // words wider than the border.
+    Word,
+}
+
+// Splits one logical line into rows that fit the border width, never inside a
+// grapheme cluster. Every row carries the char offset it starts at inside the
+// line.
+pub fn get_split(
+    value: &str,
+    get_sizes: &GlyphCache,
+    border_size: &BorderSize,
+    wrap_mode: WrapMode,
+) -> Vec<(usize, String)> {
+    let width = border_size.width as i64;
+
+    // (char offset, byte offset) of every row start.
+    let mut starts = vec![(0, 0)];
+    let mut total = 0;
+    let mut char_index = 0;
+
+    let mut opportunities = linebreaks(value).map(|(byte, _)| byte).peekable();
+    // (char offset, byte offset, width of the row before it) of the last place
+    // in the current row where the line may be broken.
+    let mut last_break: Option<(usize, usize, i64)> = None;
+
+    // The whole line is shaped at once, so rows are measured as the glyphs
+    // join inside the line.
+    let rtl = paragraph_rtl(value).unwrap_or(false);
+    let widths = grapheme_widths(value, &shape(value, rtl, get_sizes));
+    let tab_stop = get_sizes.tab_stop();
+
+    for ((byte_index, grapheme), &shaped) in value.grapheme_indices(true).zip(&widths) {
+        let row_start = starts.last().unwrap().1;
+        // Tab stops count from the start of the row, as rows are drawn.
+        let advance = if grapheme == "\t" {
+            tab_stop - total % tab_stop
+        } else {
+            shaped
+        };
+
+        if wrap_mode == WrapMode::Word {
+            while opportunities.next_if(|&byte| byte < byte_index).is_some() {}
+            if opportunities.peek() == Some(&byte_index) && byte_index > row_start {
+                last_break = Some((char_index, byte_index, total));
+            }
+        }
+
+        // Whitespace is allowed to hang past the border in word mode.
+        let hangs = wrap_mode == WrapMode::Word && grapheme.trim().is_empty();
+
+        if wrap_mode != WrapMode::None
+            && !hangs
+            && total + advance > width
+            && byte_index > row_start
+        {
+            match last_break.take() {
+                Some((break_char, break_byte, break_total)) => {
+                    starts.push((break_char, break_byte));
+                    total -= break_total;
+
+                    if total + advance > width && byte_index > break_byte {
+                        starts.push((char_index, byte_index));
+                        total = 0;
+                    }
+                }
+                None => {
+                    starts.push((char_index, byte_index));
+                    total = 0;
+                }
+            }
+        }
+
+        total += advance;
+        char_index += grapheme.chars().count();
+    }
+
+    starts
+        .iter()
+        .enumerate()
+        .map(|(index, (start_char, start_byte))| {
+            let end_byte = starts.get(index + 1).map_or(value.len(), |next| next.1);
+            (*start_char, String::from(&value[*start_byte..end_byte]))
+        })
+        .collect()
+}
+
+// Fenwick tree over the number of wrapped rows of every logical line, so the
+// first row of a line and the line of a row are found in O(log n).
+#[derive(Debug, Default)]
+struct RowIndex {
+    tree: Vec<usize>,
+}
+
+impl RowIndex {
+    fn build(counts: impl Iterator<Item = usize>) -> RowIndex {
+        let mut tree: Vec<usize> = std::iter::once(0).chain(counts).collect();
+
+        for index in 1..tree.len() {
+            let parent = index + (index & index.wrapping_neg());
+            if parent < tree.len() {
+                tree[parent] += tree[index];
+            }
+        }
+
+        RowIndex { tree }
+    }
+
+    fn add(&mut self, line: usize, delta: isize) {
+        let mut index = line + 1;
+        while index < self.tree.len() {
+            self.tree[index] = (self.tree[index] as isize + delta) as usize;
+            index += index & index.wrapping_neg();
+        }
+    }
+
+    // Number of rows before the line.
+    fn prefix(&self, line: usize) -> usize {
+        let mut index = line;
+        let mut sum = 0;
+        while index > 0 {
+            sum += self.tree[index];
+            index -= index & index.wrapping_neg();
+        }
+        sum
+    }
+
+    // The line that row `y` belongs to and how many rows come before it.
+    fn find(&self, y: usize) -> (usize, usize) {
+        let mut line = 0;
+        let mut before = 0;
+        let mut step = (self.tree.len()).next_power_of_two();
+
+        while step > 0 {
+            let next = line + step;
+            if next < self.tree.len() && before + self.tree[next] <= y {
+                line = next;
+                before += self.tree[next];
+            }
+            step >>= 1;
+        }
+
+        (line, before)
+    }
+}
+
+// Wrapped rows cached per logical line. An edit only re-wraps the lines it
+// touched; everything is re-wrapped when the width, wrap mode or font change.
+#[derive(Debug, Default)]
+pub struct Layout {
+    lines: Vec<Vec<(usize, String)>>,
+    row_index: RowIndex,
+    row_count: usize,
+}
+
+impl Layout {
+    pub fn new(
+        document: &Document,
+        get_font_size: &GlyphCache,
+        get_border_size: &BorderSize,
+        wrap_mode: WrapMode,
+    ) -> Layout {
+        let lines: Vec<Vec<(usize, String)>> = (0..document.len_lines())
+            .map(|index| {
+                get_split(
+                    &document.line(index),
+                    get_font_size,
+                    get_border_size,
+                    wrap_mode,
+                )
+            })
+            .collect();
+
+        let mut layout = Layout {
+            lines,
+            row_index: RowIndex::default(),
+            row_count: 0,
+        };
+        layout.reindex();
+        layout
+    }
+
+    fn reindex(&mut self) {
+        self.row_index = RowIndex::build(self.lines.iter().map(|rows| rows.len()));
+        self.row_count = self.lines.iter().map(|rows| rows.len()).sum();
+    }
+
+    // Applies the line changes of one or more edits, in order. The structure
+    // is updated first and the touched lines are split afterwards, because the
+    // document only holds the text after the last edit.
+    pub fn update(
+        &mut self,
+        changes: &[LineChange],
+        document: &Document,
+        get_font_size: &GlyphCache,
+        get_border_size: &BorderSize,
+        wrap_mode: WrapMode,
+    ) {
+        let mut dirty: Vec<usize> = vec![];
+        let mut line_count_changed = false;
+
+        for change in changes {
+            let removed_end = change.line + change.removed;
+            let delta = change.inserted as isize - change.removed as isize;
+
+            dirty.retain(|line| *line < change.line || *line >= removed_end);
+            for line in dirty.iter_mut() {
+                if *line >= removed_end {
+                    *line = (*line as isize + delta) as usize;
+                }
+            }
+            dirty.extend(change.line..change.line + change.inserted);
+
+            if delta != 0 {
+                self.lines.splice(
+                    change.line..removed_end,
+                    (0..change.inserted).map(|_| vec![]),
+                );
+                line_count_changed = true;
+            }
+        }
+
+        dirty.sort_unstable();
+        dirty.dedup();
+
+        for line in dirty {
+            let rows = get_split(
+                &document.line(line),
+                get_font_size,
+                get_border_size,
+                wrap_mode,
+            );
+
+            if !line_count_changed {
+                let delta = rows.len() as isize - self.lines[line].len() as isize;
+                self.row_index.add(line, delta);
+                self.row_count = (self.row_count as isize + delta) as usize;
+            }
+            self.lines[line] = rows;
+        }
+
+        if line_count_changed {
+            self.reindex();
+        }
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.row_count
+    }
+
+    // First wrapped row of a logical line.
+    pub fn line_to_row(&self, line: usize) -> usize {
+        self.row_index.prefix(line)
+    }
+
+    // Logical line of a wrapped row and the index of the row inside it.
+    pub fn row_to_line(&self, y: usize) -> (usize, usize) {
+        let (line, before) = self.row_index.find(y);
+        (line, y - before)
+    }
+
+    pub fn row(&self, y: usize) -> Buf {
+        let (line, index) = self.row_to_line(y);
+        let (start, text) = &self.lines[line][index];
+
+        Buf {
+            text: text.clone(),
+            link: if index == 0 { None } else { Some(line) },
+            line: Some(line),
+            start: *start,
+            rtl: self.lines[line]
+                .iter()
+                .find_map(|(_, text)| paragraph_rtl(text))
+                .unwrap_or(false),
+        }
+    }
+
+    pub fn rows(&self, range: Range<usize>) -> Vec<Buf> {
+        range.map(|y| self.row(y)).collect()
+    }
+
+    // Whether the row continues the logical line of the row above it.
+    pub fn is_continuation(&self, y: usize) -> bool {
+        y < self.row_count && self.row_to_line(y).1 > 0
+    }
+}
+
+// Maps a char position in the document to a wrapped row and a grapheme column
+// in it. A position on a wrap boundary belongs to the start of the next row.
+pub fn get_cursor(layout: &Layout, document: &Document, position: usize) -> Cursor {
+    let line = document.char_to_line(position);
+    let column = position - document.line_to_char(line);
+
+    let rows = &layout.lines[line];
+    let index = rows
+        .iter()
+        .rposition(|(start, _)| *start <= column)
+        .unwrap_or(0);
+    let (start, text) = &rows[index];
+
+    Cursor {
+        x: char_to_grapheme(text, column - start),
+        y: layout.line_to_row(line) + index,
+    }
+}
+
+// Inverse of get_cursor, the column is clamped to the row length.
+pub fn get_position(layout: &Layout, document: &Document, cursor: &Cursor) -> usize {
+    let (line, index) = layout.row_to_line(cursor.y);
+    let (start, text) = &layout.lines[line][index];
+
+    let x = cursor.x.min(grapheme_count(text));
+
+    document.line_to_char(line) + start + grapheme_to_char(text, x)
+}
+
+// Pixel offset of the caret at a grapheme column from the start of the row.
+pub fn row_x(row: &Buf, x: usize, get_sizes: &GlyphCache) -> i64 {
+    let carets = row_carets(row, get_sizes);
+    carets[x.min(carets.len() - 1)].0
+}
+
+// Grapheme column whose caret is closest to the pixel offset: the cluster
+// under it, before or after it depending on the half it falls in and the
+// direction of the cluster.
+pub fn row_column_at(row: &Buf, pixel_x: i64, get_sizes: &GlyphCache) -> usize {
+    let edges = grapheme_edges(row, get_sizes);
+
+    let under = edges
+        .iter()
+        .enumerate()
+        .filter(|(_, &(_, right, _))| pixel_x < right)
+        .min_by_key(|(_, &(_, right, _))| right)
+        .or_else(|| {
+            edges
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, &(_, right, _))| right)
+        });
+
+    match under {
+        Some((index, &(left, right, rtl))) => {
+            let right_half = pixel_x >= (left + right) / 2;
+            if right_half != rtl {
+                index + 1
+            } else {
+                index
+            }
+        }
+        None => 0,
+    }
+}
+
+pub fn line_height(get_sizes: &GlyphCache) -> i64 {
+    (get_sizes.ascender() - get_sizes.descender()).max(1) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font_loader::create_font_map;
+    use crate::history::{apply_edit, Edit, EditKind, History};
+    use crate::selection::Selection;
+
+    fn font() -> GlyphCache {
+        create_font_map(concat!(env!("CARGO_MANIFEST_DIR"), "/Lato-Regular.ttf"))
+    }
+
+    fn split(text: &str, width: i64, wrap_mode: WrapMode, get_sizes: &GlyphCache) -> Vec<String> {
+        let border_size = BorderSize {
+            width: width as i32,
+            height: 100,
+        };
+        get_split(text, get_sizes, &border_size, wrap_mode)
+            .into_iter()
+            .map(|(_, row)| row)
+            .collect()
+    }
+
+    #[test]
+    fn rows_are_measured_against_the_border() {
+        let font = font();
+        let text = "the quick brown fox";
+
+        assert_eq!(split(text, 300, WrapMode::Word, &font), vec![text]);
+        assert_eq!(split(text, 300, WrapMode::Char, &font), vec![text]);
+
+        let width = font.measure("the quick ").max(font.measure("brown fox"));
+        assert_eq!(
+            split(text, width, WrapMode::Word, &font),
+            vec!["the quick ", "brown fox"]
+        );
+    }
+
+    #[test]
+    fn long_words_fall_back_to_char_breaks() {
+        let font = font();
+        let width = font.measure("abcd");
+
+        let rows = split("ab abcdefgh", width, WrapMode::Word, &font);
+        assert_eq!(rows[0], "ab ");
+        assert!(rows.len() > 2);
+        assert!(rows.iter().all(|row| font.measure(row.trim_end()) <= width));
+        assert_eq!(rows.concat(), "ab abcdefgh");
+
+        assert_eq!(
+            split("ab abcdefgh", width, WrapMode::None, &font),
+            vec!["ab abcdefgh"]
+        );
+    }
+
+    #[test]
+    fn tabs_reach_the_next_stop() {
+        let font = font();
+        let stop = font.tab_stop();
+
+        // A tab after one letter still ends at the first stop, so two of them
+        // fill two stops exactly.
+        assert_eq!(
+            split("a\tb\t", stop * 2, WrapMode::Char, &font),
+            vec!["a\tb\t"]
+        );
+        assert_eq!(
+            split("a\tb\tc", stop * 2, WrapMode::Char, &font),
+            vec!["a\tb\t", "c"]
+        );
+    }
+
+    fn rows(layout: &Layout) -> Vec<(String, Option<usize>, usize)> {
+        layout
+            .rows(0..layout.row_count())
+            .into_iter()
+            .map(|row| (row.text, row.line, row.start))
+            .collect()
+    }
+
+    #[test]
+    fn updates_match_a_full_layout() {
+        let font = font();
+        let border_size = BorderSize {
+            width: font.measure("the quick ") as i32,
+            height: 100,
+        };
+        let mut document = Document::from("the quick brown fox\njumps over\nthe lazy dog");
+        document.take_changes();
+        let mut layout = Layout::new(&document, &font, &border_size, WrapMode::Word);
+        let mut history = History::new();
+
+        let check = |document: &mut Document, layout: &mut Layout| {
+            layout.update(
+                &document.take_changes(),
+                document,
+                &font,
+                &border_size,
+                WrapMode::Word,
+            );
+            let full = Layout::new(document, &font, &border_size, WrapMode::Word);
+            assert_eq!(rows(layout), rows(&full));
+            assert_eq!(layout.row_count(), full.row_count());
+        };
+
+        // Splits a line, then joins two lines and adds some in one step.
+        let steps = vec![
+            vec![Edit::Insert {
+                position: 9,
+                text: String::from("\n"),
+            }],
+            vec![
+                Edit::Remove {
+                    position: 20,
+                    text: String::from("\n"),
+                },
+                Edit::Insert {
+                    position: 0,
+                    text: String::from("a much longer first line\nand more\n"),
+                },
+            ],
+        ];
+
+        for edits in steps {
+            for edit in &edits {
+                apply_edit(&mut document, edit);
+            }
+            let selections = vec![Selection::point(0)];
+            history.record(edits, EditKind::Other, selections.clone(), selections);
+            check(&mut document, &mut layout);
+        }
+
+        while history.undo(&mut document).is_some() {
+            check(&mut document, &mut layout);
+        }
+        assert_eq!(
+            document.text(),
+            "the quick brown fox\njumps over\nthe lazy dog"
+        );
+    }
+}
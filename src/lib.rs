@@ -0,0 +1,1298 @@
+extern crate freetype as ft;
+
+pub mod atlas;
+pub mod bidi;
+pub mod document;
+pub mod file;
+pub mod font_loader;
+pub mod grapheme;
+pub mod history;
+pub mod layout;
+pub mod movement;
+pub mod sdf;
+pub mod selection;
+pub mod shaping;
+pub mod tessellate;
+pub mod viewport;
+
+use document::Document;
+use file::{read_file, write_file, FileError, LineEnding};
+use font_loader::GlyphCache;
+use grapheme::{next_grapheme_boundary, prev_grapheme_boundary};
+use history::{apply_edit, Edit, EditKind, History};
+use layout::{get_cursor, get_position, line_height, row_carets, Layout, WrapMode};
+use movement::{CaretMovement, Movement};
+use selection::{normalize_selections, word_range, Selection};
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use unicode_segmentation::UnicodeSegmentation;
+use viewport::Viewport;
+
+const ZOOM_STEP: f32 = 1.1;
+const MIN_POINT_SIZE: f32 = 4.0;
+const MAX_POINT_SIZE: f32 = 200.0;
+
+#[derive(Debug)]
+pub struct BorderSize {
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Debug)]
+pub enum KeyCommand {
+    Move(Movement),
+    Select(Movement),
+    SelectAll,
+    AddCursorAbove,
+    AddCursorBelow,
+    AddNextOccurrence,
+    Back,
+    Delete,
+    DeleteWordLeft,
+    DeleteWordRight,
+    DeleteLine,
+    Value(String),
+    // Text from the clipboard, which can span lines.
+    Paste(String),
+    // Removes the selected text, once it is on the clipboard.
+    Cut,
+    NewLine,
+    Undo,
+    Redo,
+    Size(i32, i32),
+    SetWrapMode(WrapMode),
+    SetCaretMovement(CaretMovement),
+    // Indents at the cursor, or every selected line, and outdents the lines.
+    Tab,
+    Outdent,
+    // Spaces between tab stops, and whether Tab inserts spaces instead.
+    SetTabWidth(usize),
+    SetSoftTabs(bool),
+    // Grow or shrink the font one step, or set it to a point size.
+    ZoomIn,
+    ZoomOut,
+    SetFontSize(f32),
+    // Resolution of the monitor the window is on.
+    SetDpi(u32),
+    // Places the cursor at a pixel of the view, or after a double or triple
+    // click selects the word or the line there.
+    Click(i64, i64, usize),
+    // Extends the selection of the last click to a pixel.
+    Drag(i64, i64),
+    // Scrolls the view by a number of rows without moving the cursor.
+    Scroll(isize),
+    // Scrolls the view to a fraction of the document, from the scrollbar.
+    ScrollTo(f32),
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub struct Buf {
+    pub text: String,
+    pub link: Option<usize>,
+    pub line: Option<usize>,
+    pub start: usize,
+    // Whether the paragraph, the whole logical line, runs right-to-left.
+    pub rtl: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cursor {
+    pub x: usize,
+    pub y: usize,
+}
+
+// Operations
+fn mut_delete_selection(
+    selection: &Selection,
+    document: &mut Document,
+    edits: &mut Vec<Edit>,
+) -> usize {
+    if !selection.is_empty() {
+        let edit = Edit::Remove {
+            position: selection.start(),
+            text: document.slice(selection.range()),
+        };
+        apply_edit(document, &edit);
+        edits.push(edit);
+    }
+    selection.start()
+}
+
+fn mut_type(selection: &Selection, document: &mut Document, value: &str) -> (Selection, Vec<Edit>) {
+    let mut edits = vec![];
+    let position = mut_delete_selection(selection, document, &mut edits);
+
+    let edit = Edit::Insert {
+        position,
+        text: value.to_string(),
+    };
+    apply_edit(document, &edit);
+    edits.push(edit);
+
+    (Selection::point(position + value.chars().count()), edits)
+}
+
+fn mut_new_line(selection: &Selection, document: &mut Document) -> (Selection, Vec<Edit>) {
+    mut_type(selection, document, "\n")
+}
+
+// Pasted text keeps the document's own line breaks, like a file read in.
+fn mut_paste(selection: &Selection, document: &mut Document, text: &str) -> (Selection, Vec<Edit>) {
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    mut_type(selection, document, &text)
+}
+
+fn mut_cut(selection: &Selection, document: &mut Document) -> (Selection, Vec<Edit>) {
+    let mut edits = vec![];
+    let position = mut_delete_selection(selection, document, &mut edits);
+    (Selection::point(position), edits)
+}
+
+// Deletes the selection, or the text between the cursor and `target` when
+// nothing is selected.
+fn mut_delete_to(
+    selection: &Selection,
+    document: &mut Document,
+    target: usize,
+) -> (Selection, Vec<Edit>) {
+    let mut edits = vec![];
+
+    if !selection.is_empty() {
+        let position = mut_delete_selection(selection, document, &mut edits);
+        return (Selection::point(position), edits);
+    }
+
+    let range = Selection {
+        anchor: target,
+        head: selection.head,
+    };
+    let position = mut_delete_selection(&range, document, &mut edits);
+
+    (Selection::point(position), edits)
+}
+
+fn mut_backspace(selection: &Selection, document: &mut Document) -> (Selection, Vec<Edit>) {
+    let target = prev_grapheme_boundary(document, selection.head);
+    mut_delete_to(selection, document, target)
+}
+
+fn mut_delete(selection: &Selection, document: &mut Document) -> (Selection, Vec<Edit>) {
+    let target = next_grapheme_boundary(document, selection.head);
+    mut_delete_to(selection, document, target)
+}
+
+fn mut_delete_word_left(selection: &Selection, document: &mut Document) -> (Selection, Vec<Edit>) {
+    let target = movement::word_left(document, selection.head);
+    mut_delete_to(selection, document, target)
+}
+
+fn mut_delete_word_right(selection: &Selection, document: &mut Document) -> (Selection, Vec<Edit>) {
+    let target = movement::word_right(document, selection.head);
+    mut_delete_to(selection, document, target)
+}
+
+// First and last logical line the selection touches.
+fn touched_lines(selection: &Selection, document: &Document) -> (usize, usize) {
+    let first_line = document.char_to_line(selection.start());
+    let mut last_line = document.char_to_line(selection.end());
+
+    // A selection ending right after a line break does not touch the next line.
+    if last_line > first_line && document.line_to_char(last_line) == selection.end() {
+        last_line -= 1;
+    }
+
+    (first_line, last_line)
+}
+
+// Removes every logical line the selection touches together with its line
+// break. The last line takes the break before it instead.
+fn mut_delete_line(selection: &Selection, document: &mut Document) -> (Selection, Vec<Edit>) {
+    let (first_line, last_line) = touched_lines(selection, document);
+
+    let mut start = document.line_to_char(first_line);
+    let end = if last_line + 1 < document.len_lines() {
+        document.line_to_char(last_line + 1)
+    } else {
+        start = start.saturating_sub(1);
+        document.len_chars()
+    };
+
+    let mut edits = vec![];
+    mut_delete_selection(
+        &Selection {
+            anchor: start,
+            head: end,
+        },
+        document,
+        &mut edits,
+    );
+
+    let position = movement::line_start(document, start);
+
+    (Selection::point(position), edits)
+}
+
+// Column of the position in its line counted in spaces, with tabs reaching to
+// the next multiple of `tab_width`.
+fn indent_column(document: &Document, position: usize, tab_width: usize) -> usize {
+    let line_start = movement::line_start(document, position);
+
+    document
+        .slice(line_start..position)
+        .graphemes(true)
+        .fold(0, |column, grapheme| {
+            if grapheme == "\t" {
+                (column / tab_width + 1) * tab_width
+            } else {
+                column + 1
+            }
+        })
+}
+
+// Inserts a tab, or with `soft_tabs` the spaces up to the next tab stop.
+fn mut_tab(
+    selection: &Selection,
+    document: &mut Document,
+    soft_tabs: bool,
+    tab_width: usize,
+) -> (Selection, Vec<Edit>) {
+    let value = if soft_tabs {
+        let column = indent_column(document, selection.start(), tab_width);
+        " ".repeat(tab_width - column % tab_width)
+    } else {
+        String::from("\t")
+    };
+    mut_type(selection, document, &value)
+}
+
+// Whether the selection holds a line break.
+fn spans_lines(selection: &Selection, document: &Document) -> bool {
+    document.char_to_line(selection.start()) != document.char_to_line(selection.end())
+}
+
+// Makes the edit `edit_line` gives at the start of every line any of the
+// selections touches, once per line, and moves all the selections along.
+fn mut_lines(
+    selections: &[Selection],
+    document: &mut Document,
+    edit_line: impl Fn(&Document, usize) -> Option<Edit>,
+) -> (Vec<Selection>, Vec<Edit>) {
+    let mut lines: Vec<usize> = selections
+        .iter()
+        .flat_map(|selection| {
+            let (first_line, last_line) = touched_lines(selection, document);
+            first_line..=last_line
+        })
+        .collect();
+    lines.sort_unstable();
+    lines.dedup();
+
+    let mut selections = selections.to_vec();
+    let mut edits = vec![];
+
+    for line in lines {
+        if let Some(edit) = edit_line(document, line) {
+            apply_edit(document, &edit);
+            for selection in selections.iter_mut() {
+                *selection = edit.map_selection(selection, false);
+            }
+            edits.push(edit);
+        }
+    }
+
+    (selections, edits)
+}
+
+// Indents every line the selections touch by one level.
+fn mut_indent(
+    selections: &[Selection],
+    document: &mut Document,
+    indent: &str,
+) -> (Vec<Selection>, Vec<Edit>) {
+    mut_lines(selections, document, |document, line| {
+        Some(Edit::Insert {
+            position: document.line_to_char(line),
+            text: indent.to_string(),
+        })
+    })
+}
+
+// Removes one level of indentation, a tab or up to `tab_width` spaces, from
+// the start of every line the selections touch.
+fn mut_outdent(
+    selections: &[Selection],
+    document: &mut Document,
+    tab_width: usize,
+) -> (Vec<Selection>, Vec<Edit>) {
+    mut_lines(selections, document, |document, line| {
+        let text = document.line(line);
+        let indent = if text.starts_with('\t') {
+            1
+        } else {
+            text.chars()
+                .take(tab_width)
+                .take_while(|&c| c == ' ')
+                .count()
+        };
+
+        if indent > 0 {
+            Some(Edit::Remove {
+                position: document.line_to_char(line),
+                text: text.chars().take(indent).collect(),
+            })
+        } else {
+            None
+        }
+    })
+}
+
+// Runs an operation at every selection, front to back. Every selection is
+// moved by the edits made at the selections before it, and the results of
+// those are moved by the edits made after them.
+fn mut_each(
+    selections: &[Selection],
+    document: &mut Document,
+    operation: impl Fn(&Selection, &mut Document) -> (Selection, Vec<Edit>),
+) -> (Vec<Selection>, Vec<Edit>) {
+    let mut new_selections: Vec<Selection> = Vec::with_capacity(selections.len());
+    let mut edits: Vec<Edit> = vec![];
+
+    for selection in selections {
+        let mapped = edits.iter().fold(*selection, |selection, edit| {
+            edit.map_selection(&selection, true)
+        });
+
+        let (new_selection, new_edits) = operation(&mapped, document);
+
+        for previous in new_selections.iter_mut() {
+            *previous = new_edits.iter().fold(*previous, |selection, edit| {
+                edit.map_selection(&selection, false)
+            });
+        }
+
+        new_selections.push(new_selection);
+        edits.extend(new_edits);
+    }
+
+    (new_selections, edits)
+}
+
+// The first match at or after `from`, wrapping around to the start.
+fn find_next(document: &Document, needle: &str, from: usize) -> Option<usize> {
+    document
+        .find(needle, from)
+        .or_else(|| document.find(needle, 0))
+}
+
+pub struct TextModel {
+    document: Document,
+    path: Option<PathBuf>,
+    line_ending: LineEnding,
+    history: History,
+    saved_revision: usize,
+    layout: Layout,
+    selections: Vec<Selection>,
+    primary: usize,
+    // Sticky pixel column of every selection while moving up and down.
+    goal_x: Option<Vec<i64>>,
+    // What the last click selected and how many clicks it took, while the
+    // mouse can still drag it.
+    clicked: Option<(Range<usize>, usize)>,
+    cursor: Cursor,
+    cursors: Vec<Cursor>,
+    border_size: BorderSize,
+    viewport: Viewport,
+    wrap_mode: WrapMode,
+    caret_movement: CaretMovement,
+    soft_tabs: bool,
+    font_measure: GlyphCache,
+}
+
+impl TextModel {
+    pub fn new(font_measure: GlyphCache) -> TextModel {
+        TextModel::with_document(Document::new(), font_measure)
+    }
+
+    pub fn with_document(document: Document, font_measure: GlyphCache) -> TextModel {
+        let mut model = TextModel {
+            document,
+            path: None,
+            line_ending: LineEnding::Lf,
+            history: History::new(),
+            saved_revision: 0,
+            layout: Layout::default(),
+            selections: vec![Selection::point(0)],
+            primary: 0,
+            goal_x: None,
+            clicked: None,
+            cursor: Cursor { x: 0, y: 0 },
+            cursors: vec![],
+            border_size: BorderSize {
+                width: 0,
+                height: 0,
+            },
+            viewport: Viewport::new(line_height(&font_measure)),
+            wrap_mode: WrapMode::Word,
+            caret_movement: CaretMovement::Visual,
+            soft_tabs: false,
+            font_measure,
+        };
+        model.relayout(true);
+        model
+    }
+
+    // Opens the file at `path`. A file that does not exist yet gives an empty
+    // document that is created on the first save.
+    pub fn open(path: &Path, font_measure: GlyphCache) -> Result<TextModel, FileError> {
+        let (document, line_ending) = match read_file(path) {
+            Ok(file) => file,
+            Err(FileError::Io(error)) if error.kind() == io::ErrorKind::NotFound => {
+                (Document::new(), LineEnding::Lf)
+            }
+            Err(error) => return Err(error),
+        };
+
+        let mut model = TextModel::with_document(document, font_measure);
+        model.path = Some(path.to_path_buf());
+        model.line_ending = line_ending;
+        Ok(model)
+    }
+
+    pub fn save(&mut self) -> Result<(), FileError> {
+        let path = self.path.clone().ok_or(FileError::NoPath)?;
+        write_file(&path, &self.document, self.line_ending)?;
+
+        self.saved_revision = self.history.revision();
+        // Typing after a save must not merge into the saved transaction.
+        self.history.seal();
+        Ok(())
+    }
+
+    pub fn save_as(&mut self, path: &Path) -> Result<(), FileError> {
+        let previous = self.path.replace(path.to_path_buf());
+
+        self.save().inspect_err(|_| self.path = previous)
+    }
+
+    pub fn apply(&mut self, command: KeyCommand) {
+        let goal_x = self.goal_x.take();
+        let clicked = self.clicked.take();
+        let mut full_layout = false;
+        let mut follow_cursor = true;
+
+        match command {
+            KeyCommand::Value(string) => {
+                let change = mut_each(
+                    &self.selections,
+                    &mut self.document,
+                    |selection, document| mut_type(selection, document, &string),
+                );
+                self.record(change, EditKind::Typing);
+            }
+            KeyCommand::Paste(text) => {
+                let change = mut_each(
+                    &self.selections,
+                    &mut self.document,
+                    |selection, document| mut_paste(selection, document, &text),
+                );
+                self.record(change, EditKind::Other);
+            }
+            KeyCommand::Cut => {
+                let change = mut_each(&self.selections, &mut self.document, mut_cut);
+                self.record(change, EditKind::Other);
+            }
+            KeyCommand::NewLine => {
+                let change = mut_each(&self.selections, &mut self.document, mut_new_line);
+                self.record(change, EditKind::Other);
+            }
+            KeyCommand::Move(movement) => self.move_each(movement, false, goal_x),
+            KeyCommand::Select(movement) => self.move_each(movement, true, goal_x),
+            KeyCommand::SelectAll => {
+                self.selections = vec![Selection {
+                    anchor: 0,
+                    head: self.document.len_chars(),
+                }];
+                self.primary = 0;
+                self.history.seal();
+            }
+            KeyCommand::AddCursorAbove => {
+                let cursor = get_cursor(&self.layout, &self.document, self.selections[0].head);
+
+                if cursor.y > 0 {
+                    let position = get_position(
+                        &self.layout,
+                        &self.document,
+                        &Cursor {
+                            x: cursor.x,
+                            y: cursor.y - 1,
+                        },
+                    );
+                    self.add_selection(Selection::point(position));
+                }
+            }
+            KeyCommand::AddCursorBelow => {
+                let last = self.selections[self.selections.len() - 1];
+                let cursor = get_cursor(&self.layout, &self.document, last.head);
+
+                if cursor.y + 1 < self.layout.row_count() {
+                    let position = get_position(
+                        &self.layout,
+                        &self.document,
+                        &Cursor {
+                            x: cursor.x,
+                            y: cursor.y + 1,
+                        },
+                    );
+                    self.add_selection(Selection::point(position));
+                }
+            }
+            KeyCommand::AddNextOccurrence => {
+                let primary = self.selections[self.primary];
+
+                if primary.is_empty() {
+                    let word = word_range(&self.document, primary.head);
+                    self.selections[self.primary] = Selection {
+                        anchor: word.start,
+                        head: word.end,
+                    };
+                    // The word can take in other cursors.
+                    self.primary = normalize_selections(&mut self.selections, self.primary);
+                    self.history.seal();
+                } else {
+                    let needle = self.document.slice(primary.range());
+                    let mut from = self.selections[self.selections.len() - 1].end();
+
+                    // Skip over matches that are already selected.
+                    for _ in 0..self.selections.len() {
+                        match find_next(&self.document, &needle, from) {
+                            Some(start) => {
+                                let end = start + primary.end() - primary.start();
+
+                                if self.selections.iter().any(|s| s.start() == start) {
+                                    from = end;
+                                } else {
+                                    self.add_selection(Selection {
+                                        anchor: start,
+                                        head: end,
+                                    });
+                                    break;
+                                }
+                            }
+                            None => break,
+                        }
+                    }
+                }
+            }
+            KeyCommand::Back => {
+                let change = mut_each(&self.selections, &mut self.document, mut_backspace);
+                self.record(change, EditKind::Deleting);
+            }
+            KeyCommand::Delete => {
+                let change = mut_each(&self.selections, &mut self.document, mut_delete);
+                self.record(change, EditKind::Deleting);
+            }
+            KeyCommand::DeleteWordLeft => {
+                let change = mut_each(&self.selections, &mut self.document, mut_delete_word_left);
+                self.record(change, EditKind::Other);
+            }
+            KeyCommand::DeleteWordRight => {
+                let change = mut_each(&self.selections, &mut self.document, mut_delete_word_right);
+                self.record(change, EditKind::Other);
+            }
+            KeyCommand::DeleteLine => {
+                let change = mut_each(&self.selections, &mut self.document, mut_delete_line);
+                self.record(change, EditKind::Other);
+            }
+            KeyCommand::Tab => {
+                let (soft_tabs, tab_width) = (self.soft_tabs, self.font_measure.tab_width());
+                // Selected lines are indented, text within a line is replaced.
+                let change = if self
+                    .selections
+                    .iter()
+                    .any(|selection| spans_lines(selection, &self.document))
+                {
+                    let indent = if soft_tabs {
+                        " ".repeat(tab_width)
+                    } else {
+                        String::from("\t")
+                    };
+                    mut_indent(&self.selections, &mut self.document, &indent)
+                } else {
+                    mut_each(
+                        &self.selections,
+                        &mut self.document,
+                        |selection, document| mut_tab(selection, document, soft_tabs, tab_width),
+                    )
+                };
+                self.record(change, EditKind::Other);
+            }
+            KeyCommand::Outdent => {
+                let tab_width = self.font_measure.tab_width();
+                let change = mut_outdent(&self.selections, &mut self.document, tab_width);
+                self.record(change, EditKind::Other);
+            }
+            KeyCommand::Undo => {
+                if let Some(selections) = self.history.undo(&mut self.document) {
+                    self.set_selections(selections);
+                }
+            }
+            KeyCommand::Redo => {
+                if let Some(selections) = self.history.redo(&mut self.document) {
+                    self.set_selections(selections);
+                }
+            }
+            KeyCommand::Size(width, height) => {
+                self.border_size = BorderSize { width, height };
+                full_layout = true;
+            }
+            KeyCommand::SetWrapMode(wrap_mode) => {
+                self.wrap_mode = wrap_mode;
+                full_layout = true;
+            }
+            KeyCommand::SetCaretMovement(caret_movement) => {
+                self.caret_movement = caret_movement;
+            }
+            KeyCommand::SetTabWidth(tab_width) => {
+                self.font_measure.set_tab_width(tab_width);
+                full_layout = true;
+            }
+            KeyCommand::SetSoftTabs(soft_tabs) => {
+                self.soft_tabs = soft_tabs;
+            }
+            KeyCommand::ZoomIn => {
+                self.set_font_size(self.font_measure.point_size() * ZOOM_STEP, None);
+                full_layout = true;
+            }
+            KeyCommand::ZoomOut => {
+                self.set_font_size(self.font_measure.point_size() / ZOOM_STEP, None);
+                full_layout = true;
+            }
+            KeyCommand::SetFontSize(point_size) => {
+                self.set_font_size(point_size, None);
+                full_layout = true;
+            }
+            KeyCommand::SetDpi(dpi) => {
+                self.set_font_size(self.font_measure.point_size(), Some(dpi));
+                full_layout = true;
+            }
+            KeyCommand::Click(x, y, count) => {
+                let range = self.click_range(self.position_at(x, y), count);
+                self.selections = vec![Selection {
+                    anchor: range.start,
+                    head: range.end,
+                }];
+                self.primary = 0;
+                self.clicked = Some((range, count));
+                self.history.seal();
+            }
+            KeyCommand::Drag(x, y) => {
+                if let Some((clicked, count)) = clicked {
+                    let range = self.click_range(self.position_at(x, y), count);
+                    // The clicked word or line stays selected, whichever
+                    // way the mouse goes.
+                    let selection = if range.start < clicked.start {
+                        Selection {
+                            anchor: clicked.end,
+                            head: range.start,
+                        }
+                    } else {
+                        Selection {
+                            anchor: clicked.start,
+                            head: range.end.max(clicked.end),
+                        }
+                    };
+                    self.selections = vec![selection];
+                    self.primary = 0;
+                    self.clicked = Some((clicked, count));
+                }
+            }
+            KeyCommand::Scroll(rows) => {
+                // Scrolling with the wheel does not let go of a drag.
+                self.clicked = clicked;
+                self.viewport.scroll_by(rows, self.layout.row_count());
+                follow_cursor = false;
+            }
+            KeyCommand::ScrollTo(fraction) => {
+                self.viewport.scroll_to(fraction, self.layout.row_count());
+                follow_cursor = false;
+            }
+            KeyCommand::None => follow_cursor = false,
+        }
+
+        self.relayout(full_layout);
+
+        self.viewport.height = self.border_size.height as i64;
+        if follow_cursor {
+            self.viewport
+                .scroll_into_view(self.cursor.y, self.layout.row_count());
+        } else {
+            // Edits elsewhere can shrink the document below the view.
+            self.viewport.scroll_by(0, self.layout.row_count());
+        }
+    }
+
+    fn record(&mut self, (selections, edits): (Vec<Selection>, Vec<Edit>), kind: EditKind) {
+        let selections_before = self.selections.clone();
+        self.selections = selections;
+        self.primary = normalize_selections(&mut self.selections, self.primary);
+        self.history
+            .record(edits, kind, selections_before, self.selections.clone());
+    }
+
+    fn move_each(&mut self, movement: Movement, extend: bool, goal_x: Option<Vec<i64>>) {
+        let goal_x = match goal_x {
+            Some(goal_x) if goal_x.len() == self.selections.len() => goal_x,
+            _ => self
+                .selections
+                .iter()
+                .map(|selection| {
+                    movement::goal_x(
+                        &self.layout,
+                        &self.document,
+                        selection.head,
+                        &self.font_measure,
+                    )
+                })
+                .collect(),
+        };
+
+        let targets: Vec<usize> = self
+            .selections
+            .iter()
+            .zip(goal_x.iter())
+            .map(|(selection, goal_x)| self.target(movement, extend, selection, *goal_x))
+            .collect();
+
+        for (selection, target) in self.selections.iter_mut().zip(targets) {
+            *selection = selection.move_to(target, extend);
+        }
+
+        if movement.is_vertical() {
+            self.goal_x = Some(goal_x);
+        }
+
+        self.primary = normalize_selections(&mut self.selections, self.primary);
+        self.history.seal();
+    }
+
+    fn target(
+        &self,
+        movement: Movement,
+        extend: bool,
+        selection: &Selection,
+        goal_x: i64,
+    ) -> usize {
+        let document = &self.document;
+        let head = selection.head;
+        let page = self.viewport.page_row_count() as isize;
+
+        match movement {
+            Movement::Left if !extend && !selection.is_empty() => selection.start(),
+            Movement::Right if !extend && !selection.is_empty() => selection.end(),
+            Movement::Left | Movement::Right if self.caret_movement == CaretMovement::Visual => {
+                movement::visual(
+                    &self.layout,
+                    document,
+                    head,
+                    movement == Movement::Right,
+                    &self.font_measure,
+                )
+            }
+            Movement::Left => prev_grapheme_boundary(document, head),
+            Movement::Right => next_grapheme_boundary(document, head),
+            Movement::WordLeft => movement::word_left(document, head),
+            Movement::WordRight => movement::word_right(document, head),
+            Movement::RowStart => movement::row_start(&self.layout, document, head),
+            Movement::RowEnd => movement::row_end(&self.layout, document, head),
+            Movement::LineStart => movement::line_start(document, head),
+            Movement::LineEnd => movement::line_end(document, head),
+            Movement::DocumentStart => 0,
+            Movement::DocumentEnd => document.len_chars(),
+            Movement::Up => self.vertical(head, -1, goal_x),
+            Movement::Down => self.vertical(head, 1, goal_x),
+            Movement::PageUp => self.vertical(head, -page, goal_x),
+            Movement::PageDown => self.vertical(head, page, goal_x),
+        }
+    }
+
+    fn vertical(&self, head: usize, count: isize, goal_x: i64) -> usize {
+        movement::vertical(
+            &self.layout,
+            &self.document,
+            head,
+            count,
+            goal_x,
+            &self.font_measure,
+        )
+    }
+
+    fn position_at(&self, x: i64, y: i64) -> usize {
+        movement::position_at(
+            &self.layout,
+            &self.document,
+            self.viewport.first_row,
+            x,
+            y,
+            &self.font_measure,
+        )
+    }
+
+    // Text selected by a click at the position: nothing, the word or the
+    // whole line with its line break.
+    fn click_range(&self, position: usize, count: usize) -> Range<usize> {
+        match count {
+            0 | 1 => position..position,
+            2 => word_range(&self.document, position),
+            _ => {
+                let end = movement::line_end(&self.document, position);
+                let end = (end + 1).min(self.document.len_chars());
+                movement::line_start(&self.document, position)..end
+            }
+        }
+    }
+
+    // Re-measures the glyphs; the caller re-wraps every line.
+    fn set_font_size(&mut self, point_size: f32, dpi: Option<u32>) {
+        let point_size = point_size.clamp(MIN_POINT_SIZE, MAX_POINT_SIZE);
+        let dpi = dpi.unwrap_or_else(|| self.font_measure.dpi());
+
+        self.font_measure.set_size(point_size, dpi);
+        self.viewport.line_height = line_height(&self.font_measure);
+    }
+
+    fn add_selection(&mut self, selection: Selection) {
+        let primary = self.selections.len();
+        self.selections.push(selection);
+        self.primary = normalize_selections(&mut self.selections, primary);
+        self.history.seal();
+    }
+
+    fn set_selections(&mut self, selections: Vec<Selection>) {
+        self.selections = selections;
+        self.primary = self.selections.len() - 1;
+    }
+
+    // Re-wraps the lines touched since the last call, or every line when `full`.
+    fn relayout(&mut self, full: bool) {
+        let changes = self.document.take_changes();
+
+        if full {
+            self.layout = Layout::new(
+                &self.document,
+                &self.font_measure,
+                &self.border_size,
+                self.wrap_mode,
+            );
+        } else {
+            self.layout.update(
+                &changes,
+                &self.document,
+                &self.font_measure,
+                &self.border_size,
+                self.wrap_mode,
+            );
+        }
+
+        self.cursors = self
+            .selections
+            .iter()
+            .map(|selection| get_cursor(&self.layout, &self.document, selection.head))
+            .collect();
+        self.cursor = self.cursors[self.primary].clone();
+    }
+
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.history.revision() != self.saved_revision
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.layout.row_count()
+    }
+
+    pub fn rows(&self, range: Range<usize>) -> Vec<Buf> {
+        self.layout.rows(range)
+    }
+
+    pub fn viewport(&self) -> &Viewport {
+        &self.viewport
+    }
+
+    // Rows the renderer needs, the first one drawn at the top of the window.
+    pub fn visible_rows(&self) -> Vec<Buf> {
+        self.layout
+            .rows(self.viewport.visible(self.layout.row_count()))
+    }
+
+    pub fn scrollbar(&self) -> (f32, f32) {
+        self.viewport.scrollbar(self.layout.row_count())
+    }
+
+    pub fn font_measure(&self) -> &GlyphCache {
+        &self.font_measure
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    // Cursor of the primary selection, the one the view follows.
+    pub fn cursor(&self) -> &Cursor {
+        &self.cursor
+    }
+
+    pub fn cursors(&self) -> &[Cursor] {
+        &self.cursors
+    }
+
+    // Pixel offset of the caret of a cursor in its row, and of the second
+    // caret where the text changes direction.
+    pub fn carets(&self, cursor: &Cursor) -> (i64, Option<i64>) {
+        let carets = row_carets(&self.layout.row(cursor.y), &self.font_measure);
+        carets[cursor.x.min(carets.len() - 1)]
+    }
+
+    // Carets of the cursors on the visible rows, with the visible row each is
+    // on counted from the top of the window.
+    pub fn visible_carets(&self) -> Vec<(usize, i64, Option<i64>)> {
+        let visible = self.viewport.visible(self.layout.row_count());
+
+        self.cursors
+            .iter()
+            .filter(|cursor| visible.contains(&cursor.y))
+            .map(|cursor| {
+                let (primary, secondary) = self.carets(cursor);
+                (cursor.y - visible.start, primary, secondary)
+            })
+            .collect()
+    }
+
+    pub fn selection(&self) -> Selection {
+        self.selections[self.primary]
+    }
+
+    pub fn selections(&self) -> &[Selection] {
+        &self.selections
+    }
+
+    pub fn has_selection(&self) -> bool {
+        self.selections
+            .iter()
+            .any(|selection| !selection.is_empty())
+    }
+
+    pub fn selected_text(&self) -> String {
+        self.selections
+            .iter()
+            .map(|selection| self.document.slice(selection.range()))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font_loader::create_font_map;
+
+    fn model(text: &str) -> TextModel {
+        let font = create_font_map(concat!(env!("CARGO_MANIFEST_DIR"), "/Lato-Regular.ttf"));
+        let mut model = TextModel::with_document(Document::from(text), font);
+        model.apply(KeyCommand::Size(2000, 300));
+        model
+    }
+
+    fn type_text(model: &mut TextModel, text: &str) {
+        for c in text.chars() {
+            model.apply(KeyCommand::Value(c.to_string()));
+        }
+    }
+
+    #[test]
+    fn typing_inserts_at_the_cursor() {
+        let mut model = model("");
+        type_text(&mut model, "ab");
+        model.apply(KeyCommand::NewLine);
+        type_text(&mut model, "c");
+
+        assert_eq!(model.document().text(), "ab\nc");
+        assert_eq!(model.selection(), Selection::point(4));
+        assert_eq!((model.cursor().x, model.cursor().y), (1, 1));
+    }
+
+    #[test]
+    fn typing_replaces_the_selection() {
+        let mut model = model("hello world");
+        model.apply(KeyCommand::Move(Movement::WordRight));
+        model.apply(KeyCommand::Select(Movement::DocumentEnd));
+        type_text(&mut model, "!");
+
+        assert_eq!(model.document().text(), "hello!");
+    }
+
+    #[test]
+    fn undo_and_redo_restore_text_and_selection() {
+        let mut model = model("");
+        type_text(&mut model, "one two");
+
+        model.apply(KeyCommand::Undo);
+        assert_eq!(model.document().text(), "");
+        assert_eq!(model.selection(), Selection::point(0));
+
+        model.apply(KeyCommand::Redo);
+        assert_eq!(model.document().text(), "one two");
+        assert_eq!(model.selection(), Selection::point(7));
+    }
+
+    #[test]
+    fn vertical_movement_keeps_the_column() {
+        let mut model = model("abcd\nx\nabcd");
+        model.apply(KeyCommand::Move(Movement::Right));
+        model.apply(KeyCommand::Move(Movement::Right));
+        model.apply(KeyCommand::Move(Movement::Right));
+
+        model.apply(KeyCommand::Move(Movement::Down));
+        assert_eq!(model.selection().head, 6);
+        model.apply(KeyCommand::Move(Movement::Down));
+        assert_eq!(model.selection().head, 10);
+    }
+
+    #[test]
+    fn cursors_type_on_every_line() {
+        let mut model = model("a\nb\nc");
+        model.apply(KeyCommand::AddCursorBelow);
+        model.apply(KeyCommand::AddCursorBelow);
+        type_text(&mut model, "-");
+
+        assert_eq!(model.document().text(), "-a\n-b\n-c");
+        assert_eq!(model.selections().len(), 3);
+
+        model.apply(KeyCommand::Undo);
+        assert_eq!(model.document().text(), "a\nb\nc");
+    }
+
+    #[test]
+    fn selecting_the_word_swallows_cursors_in_it() {
+        let mut model = model("hello world");
+        model.selections = vec![Selection::point(1), Selection::point(3)];
+        model.apply(KeyCommand::AddNextOccurrence);
+
+        assert_eq!(model.selections(), &[Selection { anchor: 0, head: 5 }]);
+
+        type_text(&mut model, "X");
+        assert_eq!(model.document().text(), "X world");
+        assert_eq!(model.selections(), &[Selection::point(1)]);
+    }
+
+    #[test]
+    fn next_occurrence_adds_the_next_match() {
+        let mut model = model("ab ab ab");
+        model.apply(KeyCommand::AddNextOccurrence);
+        model.apply(KeyCommand::AddNextOccurrence);
+        type_text(&mut model, "c");
+
+        assert_eq!(model.document().text(), "c c ab");
+    }
+
+    #[test]
+    fn combining_marks_stay_with_their_letter() {
+        let mut model = model("");
+        type_text(&mut model, "e\u{301}x");
+        assert_eq!(model.document().text(), "e\u{301}x");
+        assert_eq!(model.selection(), Selection::point(3));
+
+        model.apply(KeyCommand::Move(Movement::Left));
+        model.apply(KeyCommand::Move(Movement::Left));
+        assert_eq!(model.selection(), Selection::point(0));
+
+        model.apply(KeyCommand::Delete);
+        assert_eq!(model.document().text(), "x");
+    }
+
+    #[test]
+    fn backspace_and_delete_remove_whole_emoji() {
+        // A family joined with zero width joiners and a flag of two regional
+        // indicators.
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let flag = "\u{1F1E9}\u{1F1EA}";
+        let mut model = model(&format!("a{}{}b", family, flag));
+
+        model.apply(KeyCommand::Move(Movement::Right));
+        model.apply(KeyCommand::Move(Movement::Right));
+        assert_eq!(model.selection(), Selection::point(6));
+
+        model.apply(KeyCommand::Back);
+        assert_eq!(model.document().text(), format!("a{}b", flag));
+        model.apply(KeyCommand::Delete);
+        assert_eq!(model.document().text(), "ab");
+    }
+
+    #[test]
+    fn delete_joins_lines() {
+        let mut model = model("ab\ncd");
+        model.apply(KeyCommand::Move(Movement::LineEnd));
+        model.apply(KeyCommand::Delete);
+        assert_eq!(model.document().text(), "abcd");
+
+        model.apply(KeyCommand::Move(Movement::DocumentEnd));
+        model.apply(KeyCommand::Delete);
+        assert_eq!(model.document().text(), "abcd");
+    }
+
+    #[test]
+    fn word_deletes_stop_at_word_ends() {
+        let mut model = model("one two three");
+        model.apply(KeyCommand::Move(Movement::DocumentEnd));
+        model.apply(KeyCommand::DeleteWordLeft);
+        assert_eq!(model.document().text(), "one two ");
+
+        model.apply(KeyCommand::Move(Movement::DocumentStart));
+        model.apply(KeyCommand::DeleteWordRight);
+        assert_eq!(model.document().text(), " two ");
+        model.apply(KeyCommand::DeleteWordRight);
+        assert_eq!(model.document().text(), " ");
+    }
+
+    #[test]
+    fn delete_line_takes_the_line_break_along() {
+        let mut model = model("a\nb\nc");
+        model.apply(KeyCommand::Move(Movement::Down));
+        model.apply(KeyCommand::DeleteLine);
+        assert_eq!(model.document().text(), "a\nc");
+        assert_eq!(model.selection(), Selection::point(2));
+
+        // The last line takes the break before it.
+        model.apply(KeyCommand::DeleteLine);
+        assert_eq!(model.document().text(), "a");
+        assert_eq!(model.selection(), Selection::point(0));
+
+        model.apply(KeyCommand::DeleteLine);
+        assert_eq!(model.document().text(), "");
+    }
+
+    #[test]
+    fn tab_indents_a_selection_that_ends_on_the_next_line() {
+        let mut model = model("abc\ndef");
+        model.apply(KeyCommand::Select(Movement::Down));
+        model.apply(KeyCommand::Tab);
+        assert_eq!(model.document().text(), "\tabc\ndef");
+
+        model.apply(KeyCommand::Outdent);
+        assert_eq!(model.document().text(), "abc\ndef");
+    }
+
+    #[test]
+    fn tab_replaces_a_selection_within_a_line() {
+        let mut model = model("abc");
+        model.apply(KeyCommand::Select(Movement::Right));
+        model.apply(KeyCommand::Tab);
+        assert_eq!(model.document().text(), "\tbc");
+    }
+
+    #[test]
+    fn lines_shared_by_selections_are_indented_once() {
+        let mut model = model("aaa\nbbb\nccc");
+        model.selections = vec![
+            Selection { anchor: 1, head: 5 },
+            Selection { anchor: 5, head: 9 },
+        ];
+        model.apply(KeyCommand::Tab);
+        assert_eq!(model.document().text(), "\taaa\n\tbbb\n\tccc");
+
+        model.apply(KeyCommand::Outdent);
+        assert_eq!(model.document().text(), "aaa\nbbb\nccc");
+        assert_eq!(
+            model.selections(),
+            &[
+                Selection { anchor: 1, head: 5 },
+                Selection { anchor: 5, head: 9 }
+            ]
+        );
+    }
+
+    #[test]
+    fn pasted_line_breaks_become_line_feeds() {
+        let mut model = model("");
+        model.apply(KeyCommand::Paste(String::from("a\r\nb\rc\n")));
+        assert_eq!(model.document().text(), "a\nb\nc\n");
+        assert_eq!(model.selection(), Selection::point(6));
+    }
+
+    #[test]
+    fn one_undo_reverts_a_paste() {
+        let mut model = model("xy");
+        model.apply(KeyCommand::Move(Movement::Right));
+        model.apply(KeyCommand::Paste(String::from("one\ntwo\nthree")));
+        assert_eq!(model.document().text(), "xone\ntwo\nthreey");
+
+        model.apply(KeyCommand::Undo);
+        assert_eq!(model.document().text(), "xy");
+        assert_eq!(model.selection(), Selection::point(1));
+    }
+
+    #[test]
+    fn cut_removes_what_was_copied() {
+        let mut model = model("one two");
+        model.apply(KeyCommand::Select(Movement::WordRight));
+        assert_eq!(model.selected_text(), "one");
+
+        model.apply(KeyCommand::Cut);
+        assert_eq!(model.document().text(), " two");
+        assert!(!model.has_selection());
+    }
+
+    #[test]
+    fn clicks_past_the_text_land_at_the_nearest_end() {
+        let mut model = model("abc\nde");
+        let line_height = model.viewport().line_height;
+
+        model.apply(KeyCommand::Click(1000, 0, 1));
+        assert_eq!(model.selection(), Selection::point(3));
+
+        // Below the last row and above the first one.
+        model.apply(KeyCommand::Click(1000, line_height * 10, 1));
+        assert_eq!(model.selection(), Selection::point(6));
+        model.apply(KeyCommand::Click(0, -line_height, 1));
+        assert_eq!(model.selection(), Selection::point(0));
+    }
+
+    #[test]
+    fn clicks_past_a_wrapped_row_stay_on_it() {
+        let mut model = model("hello world");
+        let width = model.font_measure().measure("hello ");
+        model.apply(KeyCommand::Size(width as i32, 300));
+
+        model.apply(KeyCommand::Click(width, 0, 1));
+        assert_eq!(model.selection(), Selection::point(5));
+        assert_eq!(model.cursor().y, 0);
+    }
+
+    #[test]
+    fn double_and_triple_clicks_select_words_and_lines() {
+        let mut model = model("one two\nthree");
+        let x = model.font_measure().measure("one t");
+
+        model.apply(KeyCommand::Click(x, 0, 2));
+        assert_eq!(model.selection(), Selection { anchor: 4, head: 7 });
+
+        // Dragging keeps the clicked word selected.
+        model.apply(KeyCommand::Drag(0, 0));
+        assert_eq!(model.selection(), Selection { anchor: 7, head: 0 });
+
+        model.apply(KeyCommand::Click(x, 0, 3));
+        assert_eq!(model.selection(), Selection { anchor: 0, head: 8 });
+    }
+}
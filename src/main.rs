@@ -1,317 +1,155 @@
-mod font_loader;
 mod opengl;
 
-use font_loader::{create_font_map, FontSize};
+use text_model::font_loader::{
+    create_font_chain, GlyphCache, DEFAULT_DPI, DEFAULT_POINT_SIZE, DEFAULT_TOLERANCE,
+};
+use text_model::movement::Movement;
+use text_model::{Buf, KeyCommand, TextModel};
 
-use dela::{delaunay, segment};
-extern crate freetype as ft;
 extern crate glfw;
 
 use std::sync::{Arc, Mutex};
 
 use glfw::{Action, Context, Key};
-use std::collections::HashMap;
+use opengl::{Rect, UpdateRows};
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::mpsc::*;
 use std::thread::Builder;
-
-#[derive(Debug)]
-struct BorderSize {
-    width: i32,
-    height: i32,
-}
-
-#[derive(Debug)]
-enum KeyCommand {
-    Left,
-    Right,
-    Back,
-    Value(String),
-    NewLine,
-    Size(i32, i32),
-    None,
-}
-
-fn get_split(
-    value: &String,
-    get_sizes: &HashMap<char, FontSize>,
-    border_size: &BorderSize,
-    cursor_distance: usize,
-    current_cursor: &Cursor,
-) -> (Vec<String>, Cursor) {
-    let mut total = 0;
-    let mut cursor = 0;
-    let mut start = 0;
-
-    let fold_res = value.chars().enumerate().fold(
-        (vec![String::with_capacity(10)], Cursor { x: 0, y: 0 }),
-        |mut acc, (index, value)| {
-            let measure = get_sizes.get(&value).unwrap();
-
-            if total + measure.advance > border_size.width as i64 {
-                acc.0.push(String::from(value));
-                cursor += 1;
-                total = 0;
-            } else {
-                acc.0[cursor].push(value);
-                total += measure.advance;
-            }
-
-            if cursor_distance > index {
-                acc.1 = Cursor {
-                    x: acc.0[cursor].chars().count(),
-                    y: cursor,
-                };
-            }
-
-            acc
-        },
-    );
-
-    fold_res
-}
-
-fn normalize(rows: &Vec<Buf>) -> Vec<Buf> {
-    let mut buffer: Vec<Buf> = vec![];
-
-    rows.iter().fold(&mut buffer, |mut acc, value| {
-        match value.link {
-            Some(index) => {
-                let mut get_last = &mut acc.last_mut().unwrap();
-                //println!("{:?}", get_last.text);
-                get_last.text.push_str(&value.text);
-            }
-            None => {
-                acc.push(Buf {
-                    text: value.text.clone(),
-                    link: None,
-                    line: None,
-                });
-            }
-        }
-        acc
-    });
-    buffer
-}
-
-fn get_cursor_distance(rows: &Vec<Buf>, cursor: &Cursor) -> (usize, usize, usize) {
-    let get_current_line = rows[cursor.y].line;
-
-    let start_index = rows
-        .iter()
-        .position(|x| x.line == get_current_line)
-        .unwrap();
-
-    let left_size = rows
-        .into_iter()
-        .enumerate()
-        .filter(|(pos, value)| {
-            if pos >= &start_index && pos < &cursor.y {
-                true
-            } else {
-                false
-            }
-        })
-        .map(|x| x.1)
-        .fold(0, |acc, value| acc + value.text.chars().count());
-
-    (left_size + cursor.x, get_current_line.unwrap(), start_index)
-}
-
-fn create_wrapped_buffer(
-    rows: &Vec<Buf>,
-    get_font_size: &HashMap<char, FontSize>,
-    get_border_size: &BorderSize,
-    cursor: &Cursor,
-    temp_cursor: &TempCursor,
-) -> (Vec<Buf>, Cursor) {
-    let mut buffer: Vec<Buf> = vec![];
-
-    let normalize_buffer = normalize(&rows);
-
-    let nc = Cursor {
-        x: temp_cursor.x + cursor.x,
-        y: temp_cursor.y + cursor.y,
-    };
-
-    let (get_distance, active_line, start_index) = get_cursor_distance(&rows, &nc);
-
-    let result = normalize_buffer.iter().enumerate().fold(
-        (vec![], Cursor { x: 0, y: cursor.y }),
-        |mut acc, (index, value)| {
-            let (mut split, new_cursor) = get_split(
-                &value.text,
-                get_font_size,
-                get_border_size,
-                get_distance,
-                cursor,
-            );
-
-            if index == active_line {
-                acc.1.x = new_cursor.x;
-                acc.1.y = acc.0.len() + new_cursor.y; //+ new_cursor.y;
-            }
-
-            let mut foo: Vec<Buf> = split
-                .iter()
-                .enumerate()
-                .map(|(pos, x)| Buf {
-                    text: x.to_string(),
-                    link: if pos == 0 { None } else { Some(index) },
-                    line: Some(index),
-                })
-                .collect();
-
-            acc.0.append(&mut foo);
-            acc
-        },
-    );
-
-    result
+use std::time::{Duration, Instant};
+
+// Width in screen coordinates of the strip on the right that acts as the
+// scrollbar, and rows scrolled per mouse wheel step.
+const SCROLLBAR_WIDTH: f64 = 12.0;
+const WHEEL_ROWS: f64 = 3.0;
+// Clicks closer than this in time and screen coordinates count as a double or
+// triple click.
+const MULTI_CLICK_TIME: Duration = Duration::from_millis(500);
+const MULTI_CLICK_DISTANCE: f64 = 4.0;
+const TRACK_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.1];
+const THUMB_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.4];
+// Width of a caret in framebuffer pixels.
+const CARET_WIDTH: f32 = 2.0;
+const CARET_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+// What the layout task reports back after every request.
+struct Status {
+    title: String,
+    point_size: f32,
+    dpi: u32,
+    tab_width: usize,
+    // Text to put on the clipboard.
+    copied: Option<String>,
+    // Save found no path, so one is asked for.
+    ask_path: bool,
+    // Thumb of the scrollbar as fractions of the track.
+    scrollbar: (f32, f32),
+    // Visible row and pixel offsets of every caret, and the row height.
+    carets: Vec<(usize, i64, Option<i64>)>,
+    line_height: i64,
 }
 
-#[derive(Debug)]
-struct Cursor {
-    x: usize,
-    y: usize,
+// What the left mouse button is doing.
+#[derive(Default)]
+struct Mouse {
+    dragging_scrollbar: bool,
+    selecting: bool,
+    // Time and place of the last click, and how many came in a row.
+    last_click: Option<(Instant, f64, f64)>,
+    clicks: usize,
 }
 
-#[derive(Debug)]
-struct TempCursor {
-    x: usize,
-    y: usize,
+enum Request {
+    Command(KeyCommand),
+    Save,
+    SaveAs(PathBuf),
+    // Sends the selected text back, and for a cut removes it.
+    Copy,
+    Cut,
 }
 
-// Operations
-fn mut_type(cursor: &Cursor, buffer: &mut Vec<Buf>, value: &String) -> Cursor {
-    let mut get_line = &mut buffer[cursor.y];
-    get_line.text.insert_str(cursor.x, value);
-    Cursor {
-        x: cursor.x + 1,
-        y: cursor.y,
+fn window_title(model: &TextModel) -> String {
+    let name = model
+        .path()
+        .and_then(|path| path.file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| String::from("untitled"));
+
+    if model.is_dirty() {
+        format!("{}* - text-model", name)
+    } else {
+        format!("{} - text-model", name)
     }
 }
 
-fn mut_new_line(cursor: &Cursor, buffer: &mut Vec<Buf>) -> (TempCursor, Cursor) {
-    let (_, active_line, _) = get_cursor_distance(&buffer, &cursor);
-    buffer.push(Buf {
-        text: String::with_capacity(3),
-        link: None,
-        line: Some(active_line + 1),
-    });
-
-    (TempCursor { y: 1, x: 0 }, Cursor { y: cursor.y, x: 0 })
-}
-
-fn mut_backspace(cursor: &Cursor, buffer: &mut Vec<Buf>) -> Cursor {
-    let mut get_line = &mut buffer[cursor.y];
-    get_line.text.remove(cursor.x - 1);
-    Cursor {
-        x: cursor.x - 1,
-        y: cursor.y,
-    }
-}
+// Fonts tried in order for chars the main font lacks are listed in
+// TEXT_MODEL_FALLBACK_FONTS, separated like PATH.
+fn load_fonts(font: &str, dpi: u32) -> GlyphCache {
+    let fallbacks: Vec<PathBuf> = env::var_os("TEXT_MODEL_FALLBACK_FONTS")
+        .map(|paths| env::split_paths(&paths).collect())
+        .unwrap_or_default();
 
-fn mut_delete_line(cursor: &Cursor, buffer: &mut Vec<Buf>) -> Cursor {
-    buffer.remove(cursor.y);
-    let get_back_line = cursor.y - 1;
-    let get_line_length = &buffer[get_back_line].text.len();
-    Cursor {
-        x: *get_line_length,
-        y: get_back_line,
-    }
+    create_font_chain(font, &fallbacks, DEFAULT_POINT_SIZE, dpi, DEFAULT_TOLERANCE)
 }
 
-fn get_row_len(buffer: &Vec<Buf>, cursor: &Cursor) {}
-
-#[derive(Debug, Clone)]
-struct Buf {
-    text: String,
-    link: Option<usize>,
-    line: Option<usize>,
-}
-
-fn text_model(
-    recv: Receiver<KeyCommand>,
+fn run_text_model(
+    mut model: TextModel,
+    recv: Receiver<Request>,
     shared_buffer: Arc<Mutex<Vec<Buf>>>,
-    send_back: Sender<bool>,
-    font_measure: HashMap<char, FontSize>,
+    send_back: Sender<Status>,
 ) {
-    let mut rows: Vec<Buf> = vec![Buf {
-        text: String::from(""),
-        link: None,
-        line: Some(1),
-    }];
-
-    let mut cursor = Cursor { x: 0, y: 0 };
-    let mut temp_cursor = TempCursor { x: 0, y: 0 };
-
-    let mut border_size = BorderSize {
-        width: 0,
-        height: 0,
-    };
-
-    let mut cmd: KeyCommand = KeyCommand::None;
-
-    loop {
-        let value = recv.recv();
-
-        match value.unwrap() {
-            KeyCommand::Value(string) => {
-                //shared_buffer.lock().unwrap().push(Buf {line:None,link:None,text: "".to_string()});
-
-                let new_cursor = mut_type(&cursor, &mut rows, &string);
-                cursor = new_cursor;
-                cmd = KeyCommand::Value("z".to_string());
-                temp_cursor = TempCursor { x: 0, y: 0 };
+    while let Ok(request) = recv.recv() {
+        let mut copied = None;
+        let mut ask_path = false;
+
+        let result = match request {
+            Request::Command(command) => {
+                model.apply(command);
+                Ok(())
             }
-            KeyCommand::NewLine => {
-                let (new_temp_cursor, new_cursor) = mut_new_line(&cursor, &mut rows);
-                cursor = new_cursor;
-                temp_cursor = new_temp_cursor;
+            Request::Copy | Request::Cut if !model.has_selection() => Ok(()),
+            Request::Copy => {
+                copied = Some(model.selected_text());
+                Ok(())
             }
-            KeyCommand::Left => {
-                //if cursor.x > 0 {
-                cursor.x -= 1;
-                //}
+            Request::Cut => {
+                copied = Some(model.selected_text());
+                model.apply(KeyCommand::Cut);
+                Ok(())
             }
-            KeyCommand::Right => {
-                //let get_current_row_len = get_row_len(&rows, &cursor);
-                //if cursor.x < 10 {
-                //cursor.x += 1;
-                //}
+            Request::Save if model.path().is_none() => {
+                ask_path = true;
+                Ok(())
             }
-            KeyCommand::Back => {
-                if cursor.x > 0 {
-                    let new_cursor = mut_backspace(&cursor, &mut rows);
-                    cursor = new_cursor;
-                } else {
-                    let get_cursor = mut_delete_line(&cursor, &mut rows);
-                    let new_cursor = mut_backspace(&get_cursor, &mut rows);
-                    cursor = new_cursor;
-                }
-                cmd = KeyCommand::Back;
-            }
-            KeyCommand::Size(width, height) => {
-                border_size = BorderSize { width, height };
-            }
-            _ => (),
-        }
-
-        let (mut get_new_buffer, new_cursor) =
-            create_wrapped_buffer(&rows, &font_measure, &border_size, &cursor, &temp_cursor);
-        rows.clear();
-        rows.append(&mut get_new_buffer);
-
-        cursor = new_cursor;
+            Request::Save => model.save(),
+            Request::SaveAs(path) => model.save_as(&path),
+        };
 
-        cmd = KeyCommand::None;
-
-        shared_buffer.lock().unwrap().clear();
-
-        let mut foo = rows.clone();
-        shared_buffer.lock().unwrap().append(&mut foo);
+        if let Err(error) = result {
+            eprintln!("Failed to save: {}", error);
+        }
 
-        send_back.send(true);
+        let mut get_buffer = shared_buffer.lock().unwrap();
+        get_buffer.clear();
+        get_buffer.extend(model.visible_rows());
+        drop(get_buffer);
+
+        let status = Status {
+            title: window_title(&model),
+            point_size: model.font_measure().point_size(),
+            dpi: model.font_measure().dpi(),
+            tab_width: model.font_measure().tab_width(),
+            copied,
+            ask_path,
+            scrollbar: model.scrollbar(),
+            carets: model.visible_carets(),
+            line_height: model.viewport().line_height,
+        };
+        if send_back.send(status).is_err() {
+            break;
+        }
     }
 }
 
@@ -325,7 +163,7 @@ fn main() {
     glfw.window_hint(glfw::WindowHint::OpenGlForwardCompat(true));
 
     let (mut window, events) = glfw
-        .create_window(300, 300, "Hello this is window", glfw::WindowMode::Windowed)
+        .create_window(300, 300, "text-model", glfw::WindowMode::Windowed)
         .expect("Failed to create GLFW window.");
 
     window.set_all_polling(true);
@@ -333,9 +171,9 @@ fn main() {
 
     gl::load_with(|symbol| window.get_proc_address(symbol) as *const _);
 
-    let (send, recv): (Sender<KeyCommand>, Receiver<KeyCommand>) = channel();
+    let (send, recv): (Sender<Request>, Receiver<Request>) = channel();
 
-    let (send_back, recv_back): (Sender<bool>, Receiver<bool>) = channel();
+    let (send_back, recv_back): (Sender<Status>, Receiver<Status>) = channel();
 
     let layout_task = Builder::new().name("layout task".to_string());
 
@@ -344,23 +182,83 @@ fn main() {
     let clone_buffer = buffer.clone();
 
     let font = "./Lato-Regular.ttf";
-    let mut font_measure: HashMap<char, FontSize> = create_font_map(&font);
+    let dpi = content_dpi(&window);
+    let font_measure = load_fonts(font, dpi);
+
+    // `--atlas` draws glyphs rasterized by FreeType and `--sdf` draws distance
+    // fields, instead of triangulated outlines.
+    let is_flag = |arg: &OsString| arg == "--atlas" || arg == "--sdf";
+    let flag = env::args_os().skip(1).find(|arg| is_flag(arg));
+    let path = env::args_os().skip(1).find(|arg| !is_flag(arg));
+
+    let model = match path {
+        Some(path) => TextModel::open(Path::new(&path), font_measure).unwrap_or_else(|error| {
+            eprintln!("Failed to open {}: {}", path.to_string_lossy(), error);
+            process::exit(1);
+        }),
+        None => TextModel::new(font_measure),
+    };
+
+    let mut title = window_title(&model);
+    window.set_title(&title);
 
     let layout_task_done = layout_task.spawn(move || {
-        text_model(recv, clone_buffer, send_back, font_measure);
+        run_text_model(model, recv, clone_buffer, send_back);
     });
 
-    let (shader_program, vao, mut some_fn) = opengl::setup(300.0, 300.0);
+    // The model wraps and hit-tests in framebuffer pixels, which is also what
+    // the renderers draw in.
+    let (width, height) = window.get_framebuffer_size();
+    send.send(Request::Command(KeyCommand::Size(width, height)))
+        .unwrap();
+    let (w, h) = (width as f32, height as f32);
+
+    let font_measure = load_fonts(font, dpi);
+    let (shader_program, vao, mut some_fn): (u32, u32, UpdateRows) = match flag {
+        Some(flag) if flag == "--atlas" => opengl::setup_atlas(w, h, font_measure),
+        Some(flag) if flag == "--sdf" => {
+            let reference = load_fonts(font, DEFAULT_DPI);
+            opengl::setup_sdf(w, h, font_measure, reference)
+        }
+        _ => opengl::setup(w, h, font_measure),
+    };
+    let (rect_program, rect_vao, mut update_rects) = opengl::setup_rects(w, h);
+    let mut vertex_count = 0;
+    let mut scrollbar = (0.0, 1.0);
+    let mut carets = vec![];
+    let mut mouse = Mouse::default();
+    // There is no file dialog, the path for Save As is typed into the title.
+    let mut save_as: Option<String> = None;
+    let mut shown_title = title.clone();
 
     while !window.should_close() {
         glfw.poll_events();
 
-        match recv_back.try_recv() {
-            Ok(_) => {
-                let read_buffer_result = buffer.lock().unwrap();
-                some_fn(read_buffer_result.len());
+        // Drags and the wheel send requests faster than frames are drawn, so
+        // only the last status is drawn. Every status still gets its clipboard
+        // text and path prompt through.
+        let mut last_status = None;
+        while let Ok(status) = recv_back.try_recv() {
+            if status.ask_path && save_as.is_none() {
+                save_as = Some(String::new());
             }
-            _ => (),
+            if let Some(text) = &status.copied {
+                window.set_clipboard_string(text);
+            }
+            last_status = Some(status);
+        }
+
+        if let Some(status) = last_status {
+            title = status.title;
+            scrollbar = status.scrollbar;
+            carets = caret_rects(&status.carets, status.line_height);
+            let read_buffer_result = buffer.lock().unwrap();
+            vertex_count = some_fn(
+                &read_buffer_result,
+                status.point_size,
+                status.dpi,
+                status.tab_width,
+            );
         }
 
         unsafe {
@@ -369,13 +267,34 @@ fn main() {
 
             gl::UseProgram(shader_program);
             gl::BindVertexArray(vao);
-            gl::DrawElements(gl::TRIANGLES, 100, gl::UNSIGNED_INT, std::ptr::null());
+            gl::DrawArrays(gl::TRIANGLES, 0, vertex_count as i32);
+        }
+
+        let mut rects = scrollbar_rects(&window, scrollbar);
+        rects.extend_from_slice(&carets);
+        let rect_count = update_rects(&rects);
+        unsafe {
+            gl::UseProgram(rect_program);
+            gl::BindVertexArray(rect_vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, rect_count as i32);
         }
 
         //let read_buffer_result = buffer.lock().unwrap();
 
         for (_, event) in glfw::flush_messages(&events) {
-            handle_window_event(&mut window, event, &send);
+            if let glfw::WindowEvent::FramebufferSize(width, height) = event {
+                resize(&[shader_program, rect_program], width, height);
+            }
+            handle_window_event(&mut window, event, &send, &mut mouse, &mut save_as);
+        }
+
+        let wanted_title = match &save_as {
+            Some(path) => format!("Save as: {}_", path),
+            None => title.clone(),
+        };
+        if wanted_title != shown_title {
+            window.set_title(&wanted_title);
+            shown_title = wanted_title;
         }
 
         window.swap_buffers();
@@ -383,46 +302,273 @@ fn main() {
     }
 }
 
+// The layout task only goes away when it panicked, and then the window has
+// nothing left to show.
+fn send_request(window: &mut glfw::Window, sender: &Sender<Request>, request: Request) {
+    if sender.send(request).is_err() {
+        window.set_should_close(true);
+    }
+}
+
 fn handle_window_event(
     window: &mut glfw::Window,
     event: glfw::WindowEvent,
-    sender: &Sender<KeyCommand>,
+    sender: &Sender<Request>,
+    mouse: &mut Mouse,
+    save_as: &mut Option<String>,
+) {
+    // Keys edit the path while it is typed, Enter saves and Escape gives up.
+    if let Some(path) = save_as {
+        match event {
+            glfw::WindowEvent::Char(character) => path.push(character),
+            glfw::WindowEvent::Key(Key::Backspace, _, Action::Press, _)
+            | glfw::WindowEvent::Key(Key::Backspace, _, Action::Repeat, _) => {
+                path.pop();
+            }
+            glfw::WindowEvent::Key(Key::Enter, _, Action::Press, _) => {
+                let path = PathBuf::from(path.trim());
+                if !path.as_os_str().is_empty() {
+                    send_request(window, sender, Request::SaveAs(path));
+                }
+                *save_as = None;
+            }
+            glfw::WindowEvent::Key(Key::Escape, _, Action::Press, _) => *save_as = None,
+            glfw::WindowEvent::Key(..) => {}
+            _ => handle_editor_event(window, event, sender, mouse),
+        }
+        return;
+    }
+
+    match event {
+        glfw::WindowEvent::Key(Key::S, _, Action::Press, modifiers)
+            if modifiers.contains(glfw::Modifiers::Control) =>
+        {
+            if modifiers.contains(glfw::Modifiers::Shift) {
+                *save_as = Some(String::new());
+            } else {
+                send_request(window, sender, Request::Save);
+            }
+        }
+        _ => handle_editor_event(window, event, sender, mouse),
+    }
+}
+
+fn handle_editor_event(
+    window: &mut glfw::Window,
+    event: glfw::WindowEvent,
+    sender: &Sender<Request>,
+    mouse: &mut Mouse,
 ) {
     match event {
         glfw::WindowEvent::Key(Key::Escape, _, Action::Press, _) => window.set_should_close(true),
         glfw::WindowEvent::Char(character) => {
-            sender.send(KeyCommand::Value(character.to_string()));
+            let command = KeyCommand::Value(character.to_string());
+            send_request(window, sender, Request::Command(command));
+        }
+        glfw::WindowEvent::ContentScale(..) => {
+            let dpi = content_dpi(window);
+            send_request(window, sender, Request::Command(KeyCommand::SetDpi(dpi)));
         }
         glfw::WindowEvent::FramebufferSize(w, h) => {
-            sender.send(KeyCommand::Size(w, h));
+            send_request(window, sender, Request::Command(KeyCommand::Size(w, h)));
+        }
+        glfw::WindowEvent::Scroll(_, y) => {
+            let rows = (-y * WHEEL_ROWS).round() as isize;
+            send_request(window, sender, Request::Command(KeyCommand::Scroll(rows)));
+        }
+        glfw::WindowEvent::MouseButton(glfw::MouseButtonLeft, Action::Press, _) => {
+            let (x, y) = window.get_cursor_pos();
+            let (width, _) = window.get_size();
+
+            if x >= width as f64 - SCROLLBAR_WIDTH {
+                mouse.dragging_scrollbar = true;
+                let command = scroll_to(window, y);
+                send_request(window, sender, Request::Command(command));
+            } else {
+                mouse.clicks = match mouse.last_click {
+                    Some((time, last_x, last_y))
+                        if time.elapsed() < MULTI_CLICK_TIME
+                            && (x - last_x).abs() <= MULTI_CLICK_DISTANCE
+                            && (y - last_y).abs() <= MULTI_CLICK_DISTANCE =>
+                    {
+                        mouse.clicks % 3 + 1
+                    }
+                    _ => 1,
+                };
+                mouse.last_click = Some((Instant::now(), x, y));
+                mouse.selecting = true;
+
+                let (x, y) = to_pixels(window, x, y);
+                let command = KeyCommand::Click(x, y, mouse.clicks);
+                send_request(window, sender, Request::Command(command));
+            }
+        }
+        glfw::WindowEvent::MouseButton(glfw::MouseButtonLeft, Action::Release, _) => {
+            mouse.dragging_scrollbar = false;
+            mouse.selecting = false;
+        }
+        glfw::WindowEvent::CursorPos(_, y) if mouse.dragging_scrollbar => {
+            let command = scroll_to(window, y);
+            send_request(window, sender, Request::Command(command));
+        }
+        glfw::WindowEvent::CursorPos(x, y) if mouse.selecting => {
+            let (x, y) = to_pixels(window, x, y);
+            send_request(window, sender, Request::Command(KeyCommand::Drag(x, y)));
+        }
+        glfw::WindowEvent::Key(Key::X, _, Action::Press, modifiers)
+            if modifiers.contains(glfw::Modifiers::Control) =>
+        {
+            send_request(window, sender, Request::Cut);
+        }
+        glfw::WindowEvent::Key(Key::C, _, Action::Press, modifiers)
+            if modifiers.contains(glfw::Modifiers::Control) =>
+        {
+            send_request(window, sender, Request::Copy);
+        }
+        glfw::WindowEvent::Key(Key::V, _, Action::Press, modifiers)
+            if modifiers.contains(glfw::Modifiers::Control) =>
+        {
+            if let Some(text) = window.get_clipboard_string() {
+                send_request(window, sender, Request::Command(KeyCommand::Paste(text)));
+            }
+        }
+        glfw::WindowEvent::Key(key, _, Action::Press, modifiers)
+        | glfw::WindowEvent::Key(key, _, Action::Repeat, modifiers) => {
+            if let Some(command) = key_command(key, modifiers) {
+                send_request(window, sender, Request::Command(command));
+            }
         }
-        glfw::WindowEvent::Key(key, _, keymod, _) => match key {
-            glfw::Key::Enter => match keymod {
-                Action::Repeat | Action::Press => {
-                    sender.send(KeyCommand::NewLine);
-                }
-                _ => (),
-            },
-            glfw::Key::Backspace => match keymod {
-                Action::Repeat | Action::Press => {
-                    sender.send(KeyCommand::Back);
-                }
-                _ => (),
-            },
-            glfw::Key::Left => match keymod {
-                Action::Repeat | Action::Press => {
-                    sender.send(KeyCommand::Left);
-                }
-                _ => (),
-            },
-            glfw::Key::Right => match keymod {
-                Action::Repeat | Action::Press => {
-                    sender.send(KeyCommand::Right);
-                }
-                _ => (),
-            },
-            _ => (),
-        },
         _ => {}
     }
 }
+
+// Draws on the whole framebuffer again, one unit of the projections per pixel.
+fn resize(shader_programs: &[u32], width: i32, height: i32) {
+    unsafe {
+        gl::Viewport(0, 0, width, height);
+    }
+    for &shader_program in shader_programs {
+        opengl::set_projection(shader_program, width as f32, height as f32);
+    }
+}
+
+// GLFW reports how much larger than usual the monitor draws things.
+fn content_dpi(window: &glfw::Window) -> u32 {
+    let (scale, _) = window.get_content_scale();
+    (DEFAULT_DPI as f32 * scale).round() as u32
+}
+
+// The cursor is reported in screen coordinates and the rows are laid out in
+// framebuffer pixels.
+fn to_pixels(window: &glfw::Window, x: f64, y: f64) -> (i64, i64) {
+    let (width, height) = window.get_size();
+    let (pixel_width, pixel_height) = window.get_framebuffer_size();
+
+    let x = x * pixel_width as f64 / width.max(1) as f64;
+    let y = y * pixel_height as f64 / height.max(1) as f64;
+    (x.floor() as i64, y.floor() as i64)
+}
+
+// The track along the right edge and the thumb on it, nothing when the whole
+// document fits.
+fn scrollbar_rects(window: &glfw::Window, (start, end): (f32, f32)) -> Vec<Rect> {
+    if start <= 0.0 && end >= 1.0 {
+        return vec![];
+    }
+
+    let (width, height) = window.get_framebuffer_size();
+    let (right, bottom) = (width as f32, height as f32);
+    let (left, _) = to_pixels(window, window.get_size().0 as f64 - SCROLLBAR_WIDTH, 0.0);
+    let left = left as f32;
+
+    vec![
+        Rect {
+            left,
+            top: 0.0,
+            right,
+            bottom,
+            color: TRACK_COLOR,
+        },
+        Rect {
+            left,
+            top: start * bottom,
+            right,
+            bottom: end * bottom,
+            color: THUMB_COLOR,
+        },
+    ]
+}
+
+// A bar at every caret over the height of its row. Where the text changes
+// direction the second caret only covers the lower half of the row.
+fn caret_rects(carets: &[(usize, i64, Option<i64>)], line_height: i64) -> Vec<Rect> {
+    let line_height = line_height as f32;
+    let caret = |x: i64, top: f32, bottom: f32| Rect {
+        left: x as f32 - CARET_WIDTH / 2.0,
+        top,
+        right: x as f32 + CARET_WIDTH / 2.0,
+        bottom,
+        color: CARET_COLOR,
+    };
+
+    let mut rects = vec![];
+    for &(row, primary, secondary) in carets {
+        let top = row as f32 * line_height;
+        rects.push(caret(primary, top, top + line_height));
+        if let Some(secondary) = secondary {
+            rects.push(caret(secondary, top + line_height / 2.0, top + line_height));
+        }
+    }
+    rects
+}
+
+fn scroll_to(window: &glfw::Window, y: f64) -> KeyCommand {
+    let (_, height) = window.get_size();
+    KeyCommand::ScrollTo((y / height.max(1) as f64) as f32)
+}
+
+fn key_command(key: Key, modifiers: glfw::Modifiers) -> Option<KeyCommand> {
+    let control = modifiers.contains(glfw::Modifiers::Control);
+    let shift = modifiers.contains(glfw::Modifiers::Shift);
+    let alt = modifiers.contains(glfw::Modifiers::Alt);
+
+    let movement = match key {
+        Key::Up if control && alt => return Some(KeyCommand::AddCursorAbove),
+        Key::Down if control && alt => return Some(KeyCommand::AddCursorBelow),
+        Key::Left if control => Movement::WordLeft,
+        Key::Right if control => Movement::WordRight,
+        Key::Left => Movement::Left,
+        Key::Right => Movement::Right,
+        Key::Up => Movement::Up,
+        Key::Down => Movement::Down,
+        Key::Home if control => Movement::DocumentStart,
+        Key::End if control => Movement::DocumentEnd,
+        Key::Home if alt => Movement::LineStart,
+        Key::End if alt => Movement::LineEnd,
+        Key::Home => Movement::RowStart,
+        Key::End => Movement::RowEnd,
+        Key::PageUp => Movement::PageUp,
+        Key::PageDown => Movement::PageDown,
+        Key::Enter => return Some(KeyCommand::NewLine),
+        Key::Tab if shift => return Some(KeyCommand::Outdent),
+        Key::Tab => return Some(KeyCommand::Tab),
+        Key::Backspace if control => return Some(KeyCommand::DeleteWordLeft),
+        Key::Backspace => return Some(KeyCommand::Back),
+        Key::Delete if control => return Some(KeyCommand::DeleteWordRight),
+        Key::Delete => return Some(KeyCommand::Delete),
+        Key::K if control && shift => return Some(KeyCommand::DeleteLine),
+        Key::Z if control && shift => return Some(KeyCommand::Redo),
+        Key::Z if control => return Some(KeyCommand::Undo),
+        Key::A if control => return Some(KeyCommand::SelectAll),
+        Key::D if control => return Some(KeyCommand::AddNextOccurrence),
+        Key::Equal | Key::KpAdd if control => return Some(KeyCommand::ZoomIn),
+        Key::Minus | Key::KpSubtract if control => return Some(KeyCommand::ZoomOut),
+        _ => return None,
+    };
+
+    if shift {
+        Some(KeyCommand::Select(movement))
+    } else {
+        Some(KeyCommand::Move(movement))
+    }
+}
@@ -0,0 +1,269 @@
+use crate::document::Document;
+use crate::font_loader::GlyphCache;
+use crate::grapheme::{next_grapheme_boundary, prev_grapheme_boundary};
+use crate::layout::{
+    get_cursor, get_position, grapheme_edges, line_height, row_carets, row_column_at, row_x, Layout,
+};
+use crate::selection::is_word_char;
+use crate::{Buf, Cursor};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Movement {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordLeft,
+    WordRight,
+    // Start and end of the wrapped row.
+    RowStart,
+    RowEnd,
+    // Start and end of the logical line.
+    LineStart,
+    LineEnd,
+    DocumentStart,
+    DocumentEnd,
+    PageUp,
+    PageDown,
+}
+
+// What Left and Right step over in text that mixes directions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaretMovement {
+    // The grapheme cluster before or after the cursor in the text.
+    Logical,
+    // The grapheme cluster on that side of the caret on screen.
+    Visual,
+}
+
+impl Movement {
+    pub fn is_vertical(&self) -> bool {
+        matches!(
+            self,
+            Movement::Up | Movement::Down | Movement::PageUp | Movement::PageDown
+        )
+    }
+}
+
+pub fn word_left(document: &Document, position: usize) -> usize {
+    let mut position = position;
+
+    while position > 0
+        && !is_word_char(document.char_at(prev_grapheme_boundary(document, position)))
+    {
+        position = prev_grapheme_boundary(document, position);
+    }
+
+    while position > 0 && is_word_char(document.char_at(prev_grapheme_boundary(document, position)))
+    {
+        position = prev_grapheme_boundary(document, position);
+    }
+
+    position
+}
+
+pub fn word_right(document: &Document, position: usize) -> usize {
+    let len = document.len_chars();
+    let mut position = position;
+
+    while position < len && !is_word_char(document.char_at(position)) {
+        position = next_grapheme_boundary(document, position);
+    }
+
+    while position < len && is_word_char(document.char_at(position)) {
+        position = next_grapheme_boundary(document, position);
+    }
+
+    position
+}
+
+pub fn row_start(layout: &Layout, document: &Document, position: usize) -> usize {
+    let cursor = get_cursor(layout, document, position);
+    get_position(layout, document, &Cursor { x: 0, y: cursor.y })
+}
+
+// The end of a row that wraps is the start of the next one, so the cursor
+// stops before the last cluster instead to stay on the same row.
+pub fn row_end(layout: &Layout, document: &Document, position: usize) -> usize {
+    let cursor = get_cursor(layout, document, position);
+    let end = get_position(
+        layout,
+        document,
+        &Cursor {
+            x: usize::MAX,
+            y: cursor.y,
+        },
+    );
+
+    if layout.is_continuation(cursor.y + 1) && end > row_start(layout, document, position) {
+        prev_grapheme_boundary(document, end)
+    } else {
+        end
+    }
+}
+
+pub fn line_start(document: &Document, position: usize) -> usize {
+    document.line_to_char(document.char_to_line(position))
+}
+
+pub fn line_end(document: &Document, position: usize) -> usize {
+    let line_index = document.char_to_line(position);
+    document.line_to_char(line_index) + document.line_len(line_index)
+}
+
+// Pixel offset of the position inside its wrapped row, used as the sticky
+// column for vertical movement.
+pub fn goal_x(
+    layout: &Layout,
+    document: &Document,
+    position: usize,
+    get_sizes: &GlyphCache,
+) -> i64 {
+    let cursor = get_cursor(layout, document, position);
+    row_x(&layout.row(cursor.y), cursor.x, get_sizes)
+}
+
+// Moves `count` wrapped rows up (negative) or down, landing as close to
+// `goal_x` as the target row allows.
+pub fn vertical(
+    layout: &Layout,
+    document: &Document,
+    position: usize,
+    count: isize,
+    goal_x: i64,
+    get_sizes: &GlyphCache,
+) -> usize {
+    let cursor = get_cursor(layout, document, position);
+    let target = cursor.y as isize + count;
+
+    if target < 0 {
+        return 0;
+    }
+    if target >= layout.row_count() as isize {
+        return document.len_chars();
+    }
+
+    row_position(layout, document, target as usize, goal_x, get_sizes)
+}
+
+// Position of the caret closest to `pixel_x` in wrapped row `y`.
+fn row_position(
+    layout: &Layout,
+    document: &Document,
+    y: usize,
+    pixel_x: i64,
+    get_sizes: &GlyphCache,
+) -> usize {
+    let x = row_column_at(&layout.row(y), pixel_x, get_sizes);
+    let position = get_position(layout, document, &Cursor { x, y });
+
+    // Landing on the end of a row that wraps would show the cursor on the
+    // next row.
+    if layout.is_continuation(y + 1) && get_cursor(layout, document, position).y != y {
+        prev_grapheme_boundary(document, position)
+    } else {
+        position
+    }
+}
+
+// Hit-tests a pixel of the rows drawn from `first_row` at the top. Pixels
+// above or below the document land in its first or last row.
+pub fn position_at(
+    layout: &Layout,
+    document: &Document,
+    first_row: usize,
+    pixel_x: i64,
+    pixel_y: i64,
+    get_sizes: &GlyphCache,
+) -> usize {
+    let y = first_row as i64 + pixel_y.div_euclid(line_height(get_sizes));
+    let y = y.max(0).min(layout.row_count() as i64 - 1);
+
+    row_position(layout, document, y as usize, pixel_x, get_sizes)
+}
+
+// Moves to the nearest caret on the right or left of the cursor in its row.
+// Past the edge of the row the cursor goes on to the row after or before it
+// in reading order.
+pub fn visual(
+    layout: &Layout,
+    document: &Document,
+    position: usize,
+    right: bool,
+    get_sizes: &GlyphCache,
+) -> usize {
+    let cursor = get_cursor(layout, document, position);
+    let row = layout.row(cursor.y);
+    let slots = caret_slots(&row, get_sizes);
+    let current = slots[cursor.x.min(slots.len() - 1)];
+
+    let target = slots
+        .iter()
+        .enumerate()
+        .filter(|(_, &x)| if right { x > current } else { x < current })
+        .min_by_key(|(_, &x)| (x - current).abs());
+
+    match target {
+        Some((x, _)) => get_position(layout, document, &Cursor { x, y: cursor.y }),
+        None if right != row.rtl => {
+            next_grapheme_boundary(document, row_end(layout, document, position))
+        }
+        None => prev_grapheme_boundary(document, row_start(layout, document, position)),
+    }
+}
+
+// Pixel offset that visual movement gives every cursor position of the row.
+// A position where the direction changes has two carets. It takes the one no
+// other position has, else the one on the side of the paragraph direction, so
+// every offset of the row is stepped on.
+fn caret_slots(row: &Buf, get_sizes: &GlyphCache) -> Vec<i64> {
+    let carets = row_carets(row, get_sizes);
+    let edges = grapheme_edges(row, get_sizes);
+    let single: HashSet<i64> = carets
+        .iter()
+        .filter(|(_, secondary)| secondary.is_none())
+        .map(|&(primary, _)| primary)
+        .collect();
+
+    carets
+        .iter()
+        .enumerate()
+        .map(|(x, &(primary, secondary))| match secondary {
+            None => primary,
+            Some(secondary) if single.contains(&primary) => secondary,
+            Some(_) if edges[x].2 == row.rtl => primary,
+            Some(secondary) if single.contains(&secondary) => primary,
+            Some(secondary) => secondary,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font_loader::create_font_map;
+    use crate::{KeyCommand, TextModel};
+
+    // Heads visited by pressing Right from the start of the text.
+    fn right_presses(text: &str) -> Vec<usize> {
+        let font = create_font_map(concat!(env!("CARGO_MANIFEST_DIR"), "/Lato-Regular.ttf"));
+        let mut model = TextModel::with_document(Document::from(text), font);
+        model.apply(KeyCommand::Size(2000, 300));
+
+        (0..text.chars().count())
+            .map(|_| {
+                model.apply(KeyCommand::Move(Movement::Right));
+                model.selection().head
+            })
+            .collect()
+    }
+
+    #[test]
+    fn visual_movement_steps_on_both_carets_of_a_direction_change() {
+        assert_eq!(
+            right_presses("abc שלום def"),
+            vec![1, 2, 3, 4, 7, 6, 5, 8, 9, 10, 11, 12]
+        );
+    }
+}
@@ -15,6 +15,16 @@ use cgmath::Matrix;
 use core::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
+use text_model::atlas::{GlyphAtlas, GlyphBitmap};
+use text_model::font_loader::{FontSize, GlyphCache};
+use text_model::layout::line_height;
+use text_model::sdf::{distance_field, SDF_SCALE};
+use text_model::shaping::shape;
+use text_model::Buf;
+
+// Uploads the visible rows at a point size, DPI and tab width and returns the
+// number of vertices to draw.
+pub type UpdateRows = Box<dyn FnMut(&[Buf], f32, u32, usize) -> usize>;
 
 const VERTEX_SOURCE: &str = r#"
             #version 430 core
@@ -36,6 +46,84 @@ void main() {
 }
 "#;
 
+const ATLAS_VERTEX_SOURCE: &str = r#"
+            #version 430 core
+            layout(location = 0) in vec2 position;
+            layout(location = 1) in vec2 uv;
+            uniform mat4 MVP;
+            out vec2 atlas_uv;
+
+            void main()
+            {
+                atlas_uv = uv;
+                gl_Position = MVP * vec4(position.x, position.y, 0.0, 1.0);
+            }
+            "#;
+
+// The atlas holds coverage, which becomes the alpha of the text color.
+const ATLAS_FRAGMENT_SOURCE: &str = r#"
+#version 430 core
+in vec2 atlas_uv;
+uniform sampler2D atlas;
+out vec4 frag_color;
+
+void main() {
+  frag_color = vec4(0.0, 0.0, 0.0, texture(atlas, atlas_uv).r);
+}
+"#;
+
+// The atlas holds distances from the outline instead, 0.5 on the edge.
+// Blending over the width of one screen pixel keeps edges sharp however
+// much the field is scaled.
+const SDF_FRAGMENT_SOURCE: &str = r#"
+#version 430 core
+in vec2 atlas_uv;
+uniform sampler2D atlas;
+out vec4 frag_color;
+
+void main() {
+  float distance = texture(atlas, atlas_uv).r - 128.0 / 255.0;
+  float width = fwidth(distance);
+  frag_color = vec4(0.0, 0.0, 0.0, smoothstep(-width, width, distance));
+}
+"#;
+
+// Rectangles carry their color in every vertex.
+const RECT_VERTEX_SOURCE: &str = r#"
+            #version 430 core
+            layout(location = 0) in vec2 position;
+            layout(location = 1) in vec4 color;
+            uniform mat4 MVP;
+            out vec4 rect_color;
+
+            void main()
+            {
+                rect_color = color;
+                gl_Position = MVP * vec4(position.x, position.y, 0.0, 1.0);
+            }
+            "#;
+
+const RECT_FRAGMENT_SOURCE: &str = r#"
+#version 430 core
+in vec4 rect_color;
+out vec4 frag_color;
+
+void main() {
+  frag_color = rect_color;
+}
+"#;
+
+// A filled rectangle drawn over the text, in pixels from the top left of the
+// window.
+#[derive(Debug, Clone, Copy)]
+pub struct Rect {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub color: [f32; 4],
+}
+
 pub fn create_shader(vertex_shader_source: &str, fragment_shader_source: &str) -> u32 {
     // build and compile our shader program
     // ------------------------------------
@@ -110,129 +198,371 @@ pub fn create_shader(vertex_shader_source: &str, fragment_shader_source: &str) -
     }
 }
 
-pub fn make_index(elements_count: u32) -> Vec<u32> {
-    let mut n: u32 = 0;
+// Maps pixels from the top left of a `w` by `h` framebuffer to clip space in
+// the program's MVP. Called again whenever the framebuffer is resized.
+pub fn set_projection(shader_program: u32, w: f32, h: f32) {
+    unsafe {
+        gl::UseProgram(shader_program);
+
+        let c_str_vert = CString::new("MVP".as_bytes()).unwrap();
+        let model_loc = gl::GetUniformLocation(shader_program, c_str_vert.as_ptr());
+        let model = cgmath::ortho(0.0, w, h, 0.0, -1.0, 1.0);
+        gl::UniformMatrix4fv(model_loc, 1, gl::FALSE, model.as_ptr());
+    }
+}
+
+// Glyph triangles of every row, moved to their pen positions. Rows are
+// stacked from the top of the window, the baseline `ascender` below the top
+// of each row.
+pub fn make_vertices(rows: &[Buf], font_measure: &GlyphCache) -> Vec<f32> {
+    let row_height = line_height(font_measure) as f32;
+    let mut vertices: Vec<f32> = vec![];
 
-    let mut x: Vec<u32> = Vec::with_capacity((elements_count as usize) * 7);
+    for (index, row) in rows.iter().enumerate() {
+        let mut pen_x = 0.0;
+        let pen_y = index as f32 * row_height + font_measure.ascender() as f32;
 
-    for _ in 0..elements_count {
-        x.append(&mut vec![n, n + 1, n + 2, n + 2, n + 3, n, 0xFFFF]);
+        for glyph in shape(&row.text, row.rtl, font_measure) {
+            let (x_offset, y_offset) = (glyph.x_offset as f32, glyph.y_offset as f32);
 
-        n = n + 4;
+            for (x, y) in glyph.size.triangles.iter() {
+                vertices.push(pen_x + x_offset + x);
+                vertices.push(pen_y + y_offset + y);
+            }
+            pen_x += glyph.advance as f32;
+        }
     }
 
-    x
+    vertices
 }
 
-pub fn setup(w: f32, h: f32) -> (u32, u32, impl FnMut(usize)) {
+pub fn setup(w: f32, h: f32, mut font_measure: GlyphCache) -> (u32, u32, UpdateRows) {
     unsafe {
         let shader_program = create_shader(VERTEX_SOURCE, FRAGMENT_SOURCE);
 
-        /*let mut vertex_buffer: Vec<f32> = vec![
-            10.0, 10.0, //0
-            50.0, 10.0, //1
-            50.0, 50.0, //2
-            10.0, 50.0,
-        ];*/
+        let (mut vbo, mut vao) = (0, 0);
+        gl::GenVertexArrays(1, &mut vao);
+        gl::GenBuffers(1, &mut vbo);
+        gl::BindVertexArray(vao);
+
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
 
-        let mut vertex_cleaner: Vec<f32> = Vec::with_capacity(800);
-        let mut vertex_buffer: Vec<f32> = Vec::with_capacity(800);
+        let stripe = 2 * mem::size_of::<GLfloat>() as GLsizei;
+        gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stripe, ptr::null());
+        gl::EnableVertexAttribArray(0);
 
-        (0..100).into_iter().for_each(|_| {
-            vertex_cleaner.append(&mut vec![
-                0.0, 0.0, //0
-                0.0, 0.0, //1
-                0.0, 0.0, //2
-                0.0, 0.0,
-            ]);
-        });
+        gl::BindVertexArray(0);
 
-        vertex_buffer.append(&mut vertex_cleaner);
+        set_projection(shader_program, w, h);
 
-        let (mut vbo, mut vao, mut ebo) = (0, 0, 0);
+        // Uploads the rows at the given font size and returns how many
+        // vertices to draw.
+        let some_fn = move |rows: &[Buf], point_size: f32, dpi: u32, tab_width: usize| {
+            if font_measure.point_size() != point_size || font_measure.dpi() != dpi {
+                font_measure.set_size(point_size, dpi);
+            }
+            font_measure.set_tab_width(tab_width);
+            let vertex_buffer = make_vertices(rows, &font_measure);
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (vertex_buffer.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
+                vertex_buffer.as_ptr() as *const c_void,
+                gl::DYNAMIC_DRAW,
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+            vertex_buffer.len() / 2
+        };
+
+        (shader_program, vao, Box::new(some_fn))
+    }
+}
+
+// One textured quad per glyph as two triangles of x, y, u, v vertices.
+// `None` when the atlas moved glyphs while the quads were made.
+fn make_quads<R>(
+    rows: &[Buf],
+    font_measure: &GlyphCache,
+    atlas: &mut GlyphAtlas,
+    texels_per_pixel: f32,
+    rasterize: &mut R,
+) -> Option<Vec<f32>>
+where
+    R: FnMut(&GlyphCache, &FontSize) -> Option<GlyphBitmap>,
+{
+    let row_height = line_height(font_measure) as f32;
+    let generation = atlas.generation();
+    let mut vertices: Vec<f32> = vec![];
+
+    atlas.begin_frame();
+
+    for (index, row) in rows.iter().enumerate() {
+        let mut pen_x = 0.0;
+        let pen_y = index as f32 * row_height + font_measure.ascender() as f32;
+
+        for shaped in shape(&row.text, row.rtl, font_measure) {
+            let size = &shaped.size;
+
+            if let Some(glyph) =
+                atlas.get((size.face, size.index), || rasterize(font_measure, size))
+            {
+                if atlas.generation() != generation {
+                    return None;
+                }
+
+                let (width, height) = atlas.size();
+                let x0 = pen_x + shaped.x_offset as f32 + glyph.left as f32 / texels_per_pixel;
+                let y0 = pen_y + shaped.y_offset as f32 + glyph.top as f32 / texels_per_pixel;
+                let x1 = x0 + glyph.width as f32 / texels_per_pixel;
+                let y1 = y0 + glyph.height as f32 / texels_per_pixel;
+                let u0 = glyph.x as f32 / width as f32;
+                let v0 = glyph.y as f32 / height as f32;
+                let u1 = (glyph.x + glyph.width) as f32 / width as f32;
+                let v1 = (glyph.y + glyph.height) as f32 / height as f32;
+
+                vertices.extend_from_slice(&[
+                    x0, y0, u0, v0, //0
+                    x1, y0, u1, v0, //1
+                    x1, y1, u1, v1, //2
+                    x1, y1, u1, v1, //2
+                    x0, y1, u0, v1, //3
+                    x0, y0, u0, v0, //0
+                ]);
+            }
+            pen_x += shaped.advance as f32;
+        }
+    }
+
+    Some(vertices)
+}
+
+// Like `setup`, but draws glyphs rasterized by FreeType from a texture atlas
+// instead of their triangulated outlines.
+pub fn setup_atlas(w: f32, h: f32, font_measure: GlyphCache) -> (u32, u32, UpdateRows) {
+    setup_textured(
+        w,
+        h,
+        font_measure,
+        ATLAS_FRAGMENT_SOURCE,
+        |_: &GlyphCache| 1.0,
+        true,
+        |font_measure: &GlyphCache, size: &FontSize| font_measure.rasterize(size.face, size.index),
+    )
+}
+
+// Draws glyphs from distance fields of their outlines, which stay sharp at
+// any size. The fields are made once from the outlines in `reference`, a
+// cache of the same fonts at a fixed size, and scaled to the size drawn.
+pub fn setup_sdf(
+    w: f32,
+    h: f32,
+    font_measure: GlyphCache,
+    reference: GlyphCache,
+) -> (u32, u32, UpdateRows) {
+    let reference_size = reference.point_size() * reference.dpi() as f32;
+
+    setup_textured(
+        w,
+        h,
+        font_measure,
+        SDF_FRAGMENT_SOURCE,
+        move |font_measure: &GlyphCache| {
+            let size = font_measure.point_size() * font_measure.dpi() as f32;
+            SDF_SCALE * reference_size / size
+        },
+        false,
+        move |_: &GlyphCache, size: &FontSize| {
+            Some(distance_field(&reference.glyph(size.face, size.index)))
+        },
+    )
+}
+
+// `texels_per_pixel` tells how large the bitmaps are drawn at the current
+// size. Bitmaps that are `per_size` are made again after zooming.
+fn setup_textured<T, R>(
+    w: f32,
+    h: f32,
+    mut font_measure: GlyphCache,
+    fragment_source: &str,
+    texels_per_pixel: T,
+    per_size: bool,
+    mut rasterize: R,
+) -> (u32, u32, UpdateRows)
+where
+    T: Fn(&GlyphCache) -> f32 + 'static,
+    R: FnMut(&GlyphCache, &FontSize) -> Option<GlyphBitmap> + 'static,
+{
+    unsafe {
+        let shader_program = create_shader(ATLAS_VERTEX_SOURCE, fragment_source);
+        let mut atlas = GlyphAtlas::new();
+
+        let (mut vbo, mut vao, mut texture) = (0, 0, 0);
         gl::GenVertexArrays(1, &mut vao);
         gl::GenBuffers(1, &mut vbo);
-        gl::GenBuffers(1, &mut ebo);
         gl::BindVertexArray(vao);
 
-        let map_flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
-        let create_flags = map_flags | gl::DYNAMIC_STORAGE_BIT;
-
         gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-        gl::BufferStorage(
-            gl::ARRAY_BUFFER,
-            (vertex_buffer.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
-            //&0f32 as *const f32 as *const c_void,
-            &vertex_buffer[0] as *const f32 as *const c_void,
-            create_flags,
+
+        let stripe = 4 * mem::size_of::<GLfloat>() as GLsizei;
+        gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stripe, ptr::null());
+        gl::EnableVertexAttribArray(0);
+        gl::VertexAttribPointer(
+            1,
+            2,
+            gl::FLOAT,
+            gl::FALSE,
+            stripe,
+            (2 * mem::size_of::<GLfloat>()) as *const c_void,
         );
+        gl::EnableVertexAttribArray(1);
 
-        let index_buffer = make_index(800);
-        //let index_buffer: Vec<u32> = vec![0, 1, 2, 2, 3, 0];
+        gl::BindVertexArray(0);
 
-        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
-        gl::BufferStorage(
-            gl::ELEMENT_ARRAY_BUFFER,
-            (index_buffer.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
-            //&0u32 as *const u32 as *const c_void,
-            &index_buffer[0] as *const u32 as *const c_void,
-            create_flags,
+        gl::GenTextures(1, &mut texture);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(
+            gl::TEXTURE_2D,
+            gl::TEXTURE_WRAP_S,
+            gl::CLAMP_TO_EDGE as GLint,
         );
+        gl::TexParameteri(
+            gl::TEXTURE_2D,
+            gl::TEXTURE_WRAP_T,
+            gl::CLAMP_TO_EDGE as GLint,
+        );
+        // Atlas rows are tightly packed bytes.
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
 
-        let pointer = gl::MapBufferRange(gl::ARRAY_BUFFER, 0, 800, map_flags);
-        let save_pointer = Some(pointer);
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
 
-        let stripe = 2 * mem::size_of::<GLfloat>() as GLsizei;
-        gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stripe, ptr::null());
-        gl::EnableVertexAttribArray(0);
+        set_projection(shader_program, w, h);
 
-        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        let c_str_atlas = CString::new("atlas".as_bytes()).unwrap();
+        let atlas_loc = gl::GetUniformLocation(shader_program, c_str_atlas.as_ptr());
+        gl::Uniform1i(atlas_loc, 0);
 
-        gl::BindVertexArray(0);
+        let some_fn = move |rows: &[Buf], point_size: f32, dpi: u32, tab_width: usize| {
+            if font_measure.point_size() != point_size || font_measure.dpi() != dpi {
+                font_measure.set_size(point_size, dpi);
+                if per_size {
+                    atlas.clear();
+                }
+            }
+            font_measure.set_tab_width(tab_width);
+            let texels_per_pixel = texels_per_pixel(&font_measure);
+
+            // An eviction halfway moves the glyphs placed before it, the
+            // second pass finds them all in their new places.
+            let mut quads = || {
+                make_quads(
+                    rows,
+                    &font_measure,
+                    &mut atlas,
+                    texels_per_pixel,
+                    &mut rasterize,
+                )
+            };
+            let vertex_buffer = quads().or_else(&mut quads).unwrap_or_default();
+
+            if atlas.take_dirty() {
+                let (width, height) = atlas.size();
+                gl::BindTexture(gl::TEXTURE_2D, texture);
+                gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::R8 as GLint,
+                    width as GLsizei,
+                    height as GLsizei,
+                    0,
+                    gl::RED,
+                    gl::UNSIGNED_BYTE,
+                    atlas.pixels().as_ptr() as *const c_void,
+                );
+            }
 
-        gl::UseProgram(shader_program);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (vertex_buffer.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
+                vertex_buffer.as_ptr() as *const c_void,
+                gl::DYNAMIC_DRAW,
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
 
-        gl::Enable(gl::PRIMITIVE_RESTART);
-        gl::PrimitiveRestartIndex(0xFFFF);
+            vertex_buffer.len() / 4
+        };
 
-        let c_str_vert = CString::new("MVP".as_bytes()).unwrap();
+        (shader_program, vao, Box::new(some_fn))
+    }
+}
 
-        let model_loc = gl::GetUniformLocation(shader_program, c_str_vert.as_ptr());
+// Two triangles of x, y, r, g, b, a vertices per rectangle.
+fn make_rects(rects: &[Rect]) -> Vec<f32> {
+    let mut vertices: Vec<f32> = vec![];
 
-        let model = cgmath::ortho(0.0, w, h, 0.0, -1.0, 1.0);
+    for rect in rects {
+        let [r, g, b, a] = rect.color;
+        let (x0, y0, x1, y1) = (rect.left, rect.top, rect.right, rect.bottom);
 
-        gl::UniformMatrix4fv(model_loc, 1, gl::FALSE, model.as_ptr());
+        for &(x, y) in &[(x0, y0), (x1, y0), (x1, y1), (x1, y1), (x0, y1), (x0, y0)] {
+            vertices.extend_from_slice(&[x, y, r, g, b, a]);
+        }
+    }
 
-        let some_fn = move |len| {
-            (0..len).into_iter().enumerate().for_each(|(pos, _)| {
-                let index = (pos + 1) as f32;
-
-                let new = vec![
-                    10.0 + index,
-                    10.0 + index, //0
-                    50.0 + index,
-                    10.0 + index, //1
-                    50.0 + index,
-                    50.0 + index, //2
-                    10.0 + index,
-                    50.0 + index,
-                ];
-
-                let start = 8 * pos;
-                let end = 8 * (pos + 1);
-
-                vertex_buffer.splice(start..end, new.iter().cloned());
-            });
-
-            for x in len * 8..vertex_buffer.len() {
-                vertex_buffer[x] = 0.0;
-            }
+    vertices
+}
+
+// Draws plain rectangles like the scrollbar over the text.
+pub fn setup_rects(w: f32, h: f32) -> (u32, u32, impl FnMut(&[Rect]) -> usize) {
+    unsafe {
+        let shader_program = create_shader(RECT_VERTEX_SOURCE, RECT_FRAGMENT_SOURCE);
 
-            ptr::copy_nonoverlapping(
-                vertex_buffer.as_ptr(),
-                save_pointer.unwrap() as *mut f32,
-                vertex_buffer.len(),
+        let (mut vbo, mut vao) = (0, 0);
+        gl::GenVertexArrays(1, &mut vao);
+        gl::GenBuffers(1, &mut vbo);
+        gl::BindVertexArray(vao);
+
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+
+        let stripe = 6 * mem::size_of::<GLfloat>() as GLsizei;
+        gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stripe, ptr::null());
+        gl::EnableVertexAttribArray(0);
+        gl::VertexAttribPointer(
+            1,
+            4,
+            gl::FLOAT,
+            gl::FALSE,
+            stripe,
+            (2 * mem::size_of::<GLfloat>()) as *const c_void,
+        );
+        gl::EnableVertexAttribArray(1);
+
+        gl::BindVertexArray(0);
+
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+
+        set_projection(shader_program, w, h);
+
+        let some_fn = move |rects: &[Rect]| {
+            let vertex_buffer = make_rects(rects);
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (vertex_buffer.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
+                vertex_buffer.as_ptr() as *const c_void,
+                gl::DYNAMIC_DRAW,
             );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+            vertex_buffer.len() / 6
         };
 
         (shader_program, vao, some_fn)
@@ -0,0 +1,119 @@
+use crate::atlas::GlyphBitmap;
+use crate::font_loader::FontSize;
+
+// Texels per outline pixel. Distance fields interpolate well, so a small
+// field still gives sharp edges when the text is drawn much larger.
+pub const SDF_SCALE: f32 = 2.0;
+// How far from the outline, in texels, the distance is still told apart
+// before it saturates at fully inside or outside.
+pub const SDF_SPREAD: f32 = 6.0;
+
+type Point = (f32, f32);
+
+fn segment_distance(point: Point, a: Point, b: Point) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length = dx * dx + dy * dy;
+
+    let t = if length > 0.0 {
+        (((point.0 - a.0) * dx + (point.1 - a.1) * dy) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    let (x, y) = (a.0 + t * dx - point.0, a.1 + t * dy - point.1);
+    (x * x + y * y).sqrt()
+}
+
+// Non-zero winding, so overlapping contours of one glyph stay filled.
+fn winding(contours: &[Vec<Point>], point: Point) -> i32 {
+    let mut winding = 0;
+
+    for contour in contours {
+        for i in 0..contour.len() {
+            let a = contour[i];
+            let b = contour[(i + 1) % contour.len()];
+            let side = (b.0 - a.0) * (point.1 - a.1) - (point.0 - a.0) * (b.1 - a.1);
+
+            if a.1 <= point.1 && b.1 > point.1 && side > 0.0 {
+                winding += 1;
+            } else if a.1 > point.1 && b.1 <= point.1 && side < 0.0 {
+                winding -= 1;
+            }
+        }
+    }
+    winding
+}
+
+// Signed distance field of the glyph outline. Texels hold 128 on the outline,
+// more inside and less outside, in steps of `SDF_SPREAD` texels per 127.
+pub fn distance_field(size: &FontSize) -> GlyphBitmap {
+    let contours: Vec<Vec<Point>> = size
+        .contours
+        .iter()
+        .filter(|contour| contour.len() >= 2)
+        .map(|contour| {
+            contour
+                .iter()
+                .map(|&(x, y)| (x * SDF_SCALE, y * SDF_SCALE))
+                .collect()
+        })
+        .collect();
+
+    if contours.is_empty() {
+        return GlyphBitmap {
+            width: 0,
+            height: 0,
+            left: 0,
+            top: 0,
+            pixels: vec![],
+        };
+    }
+
+    let points = contours.iter().flatten();
+    let (min_x, min_y, max_x, max_y) = points.fold(
+        (f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+        |(min_x, min_y, max_x, max_y), &(x, y)| {
+            (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))
+        },
+    );
+
+    let left = (min_x - SDF_SPREAD).floor() as i32;
+    let top = (min_y - SDF_SPREAD).floor() as i32;
+    let width = ((max_x + SDF_SPREAD).ceil() as i32 - left) as u32;
+    let height = ((max_y + SDF_SPREAD).ceil() as i32 - top) as u32;
+
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+
+    for row in 0..height {
+        for column in 0..width {
+            let point = (
+                left as f32 + column as f32 + 0.5,
+                top as f32 + row as f32 + 0.5,
+            );
+
+            let mut distance = f32::MAX;
+            for contour in &contours {
+                for i in 0..contour.len() {
+                    let edge =
+                        segment_distance(point, contour[i], contour[(i + 1) % contour.len()]);
+                    distance = distance.min(edge);
+                }
+            }
+
+            if winding(&contours, point) == 0 {
+                distance = -distance;
+            }
+
+            let value = 128.0 + distance / SDF_SPREAD * 127.0;
+            pixels.push(value.clamp(0.0, 255.0) as u8);
+        }
+    }
+
+    GlyphBitmap {
+        width,
+        height,
+        left,
+        top,
+        pixels,
+    }
+}
@@ -0,0 +1,164 @@
+use crate::document::Document;
+use crate::grapheme::{next_grapheme_boundary, prev_grapheme_boundary};
+use std::ops::Range;
+
+// A selected range of the document. The anchor stays where the selection was
+// started, the head is the end that moves and is where the cursor is drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Selection {
+    pub anchor: usize,
+    pub head: usize,
+}
+
+impl Selection {
+    pub fn point(position: usize) -> Selection {
+        Selection {
+            anchor: position,
+            head: position,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    pub fn start(&self) -> usize {
+        self.anchor.min(self.head)
+    }
+
+    pub fn end(&self) -> usize {
+        self.anchor.max(self.head)
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start()..self.end()
+    }
+
+    // Moves the head, keeping the anchor when extending.
+    pub fn move_to(&self, position: usize, extend: bool) -> Selection {
+        if extend {
+            Selection {
+                anchor: self.anchor,
+                head: position,
+            }
+        } else {
+            Selection::point(position)
+        }
+    }
+}
+
+// Sorts the selections and merges the ones that overlap. Returns the new index
+// of the primary selection.
+pub fn normalize_selections(selections: &mut Vec<Selection>, primary: usize) -> usize {
+    let primary_head = selections[primary].head;
+
+    selections.sort_by_key(|selection| (selection.start(), selection.end()));
+
+    let mut merged: Vec<Selection> = Vec::with_capacity(selections.len());
+
+    for selection in selections.drain(..) {
+        match merged.last_mut() {
+            Some(last) if selection.start() < last.end() || selection.start() == last.start() => {
+                let start = last.start();
+                let end = last.end().max(selection.end());
+
+                *last = if last.head >= last.anchor {
+                    Selection {
+                        anchor: start,
+                        head: end,
+                    }
+                } else {
+                    Selection {
+                        anchor: end,
+                        head: start,
+                    }
+                };
+            }
+            _ => merged.push(selection),
+        }
+    }
+
+    *selections = merged;
+
+    selections
+        .iter()
+        .position(|selection| selection.start() <= primary_head && primary_head <= selection.end())
+        .unwrap_or(selections.len() - 1)
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Range of the word touching the position, empty when there is none.
+pub fn word_range(document: &Document, position: usize) -> Range<usize> {
+    let mut start = position;
+    let mut end = position;
+
+    while start > 0 && is_word_char(document.char_at(prev_grapheme_boundary(document, start))) {
+        start = prev_grapheme_boundary(document, start);
+    }
+
+    while end < document.len_chars() && is_word_char(document.char_at(end)) {
+        end = next_grapheme_boundary(document, end);
+    }
+
+    start..end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(anchor: usize, head: usize) -> Selection {
+        Selection { anchor, head }
+    }
+
+    #[test]
+    fn selections_are_sorted() {
+        let mut selections = vec![Selection::point(8), Selection::point(2), range(4, 5)];
+        let primary = normalize_selections(&mut selections, 0);
+
+        assert_eq!(
+            selections,
+            vec![Selection::point(2), range(4, 5), Selection::point(8)]
+        );
+        assert_eq!(primary, 2);
+    }
+
+    #[test]
+    fn overlapping_selections_merge() {
+        let mut selections = vec![range(0, 4), range(6, 2), range(10, 12)];
+        let primary = normalize_selections(&mut selections, 1);
+
+        assert_eq!(selections, vec![range(0, 6), range(10, 12)]);
+        assert_eq!(primary, 0);
+    }
+
+    #[test]
+    fn merging_keeps_the_direction() {
+        let mut selections = vec![range(4, 0), range(6, 3)];
+        normalize_selections(&mut selections, 0);
+
+        assert_eq!(selections, vec![range(6, 0)]);
+    }
+
+    #[test]
+    fn touching_selections_stay_apart() {
+        let mut selections = vec![range(0, 3), range(3, 5), Selection::point(5)];
+        normalize_selections(&mut selections, 0);
+
+        assert_eq!(
+            selections,
+            vec![range(0, 3), range(3, 5), Selection::point(5)]
+        );
+    }
+
+    #[test]
+    fn equal_cursors_merge() {
+        let mut selections = vec![Selection::point(3), Selection::point(3)];
+        normalize_selections(&mut selections, 1);
+
+        assert_eq!(selections, vec![Selection::point(3)]);
+    }
+}
@@ -0,0 +1,202 @@
+use crate::bidi::visual_runs;
+use crate::font_loader::{FontSize, GlyphCache};
+use std::ops::Range;
+use std::sync::Arc;
+use unicode_segmentation::UnicodeSegmentation;
+
+// A glyph of shaped text. It is drawn offset from the pen position, which
+// then moves on by `advance`.
+#[derive(Debug, Clone)]
+pub struct ShapedGlyph {
+    pub size: Arc<FontSize>,
+    // Byte offset in the text of the cluster the glyph was shaped from.
+    pub cluster: usize,
+    pub advance: i64,
+    pub x_offset: i64,
+    // With y growing downwards, like the outlines.
+    pub y_offset: i64,
+}
+
+// Longest run in grapheme clusters. Longer words are shaped in pieces, which
+// only loses kerning and ligatures where they are cut.
+const MAX_RUN_LENGTH: usize = 64;
+
+// Turns text into positioned glyphs, in visual order from the left. `rtl` is
+// the direction of the paragraph the text is in. Every run of one direction
+// is shaped word by word.
+pub fn shape(text: &str, rtl: bool, get_sizes: &GlyphCache) -> Vec<ShapedGlyph> {
+    let mut glyphs = vec![];
+
+    for (range, run_rtl) in visual_runs(text, rtl) {
+        let mut words = words(&text[range.clone()], get_sizes);
+        if run_rtl {
+            words.reverse();
+        }
+
+        for (face, word) in words {
+            let (start, end) = (range.start + word.start, range.start + word.end);
+            shape_run(
+                &text[start..end],
+                start,
+                face,
+                run_rtl,
+                get_sizes,
+                &mut glyphs,
+            );
+        }
+    }
+
+    // Tabs reach to the next tab stop from the start of the text.
+    let tab_stop = get_sizes.tab_stop();
+    let mut pen = 0;
+    for glyph in glyphs.iter_mut() {
+        if text.as_bytes()[glyph.cluster] == b'\t' {
+            glyph.advance = tab_stop - pen % tab_stop;
+        }
+        pen += glyph.advance;
+    }
+
+    glyphs
+}
+
+// Splits the text into runs of grapheme clusters whose first chars come from
+// the same face of the chain, ending after whitespace so the runs are words
+// that can be cached.
+fn words(text: &str, get_sizes: &GlyphCache) -> Vec<(usize, Range<usize>)> {
+    let mut runs: Vec<(usize, Range<usize>)> = vec![];
+    let mut word_ended = false;
+    let mut length = 0;
+
+    for (byte_index, grapheme) in text.grapheme_indices(true) {
+        let face = grapheme.chars().next().map_or(0, |c| get_sizes.get(c).face);
+        let end = byte_index + grapheme.len();
+        let is_space = grapheme.trim().is_empty();
+
+        match runs.last_mut() {
+            Some((run_face, range))
+                if *run_face == face && (!word_ended || is_space) && length < MAX_RUN_LENGTH =>
+            {
+                range.end = end;
+                length += 1;
+            }
+            _ => {
+                runs.push((face, byte_index..end));
+                length = 1;
+            }
+        }
+        word_ended = is_space;
+    }
+
+    runs
+}
+
+fn shape_run(
+    text: &str,
+    start: usize,
+    face: usize,
+    rtl: bool,
+    get_sizes: &GlyphCache,
+    glyphs: &mut Vec<ShapedGlyph>,
+) {
+    let word = get_sizes.shaped_word(face, rtl, text, || shape_word(text, face, rtl, get_sizes));
+
+    glyphs.extend(word.iter().map(|glyph| ShapedGlyph {
+        cluster: start + glyph.cluster,
+        ..glyph.clone()
+    }));
+}
+
+fn shape_word(text: &str, face: usize, rtl: bool, get_sizes: &GlyphCache) -> Vec<ShapedGlyph> {
+    let font = match rustybuzz::Face::from_slice(get_sizes.font(face), 0) {
+        Some(font) => font,
+        None => return unshaped(text, rtl, get_sizes),
+    };
+
+    // Tabs are shaped as spaces and widened afterwards. Both are one byte, so
+    // the clusters still point into the text.
+    let mut buffer = rustybuzz::UnicodeBuffer::new();
+    buffer.push_str(&text.replace('\t', " "));
+    buffer.set_direction(if rtl {
+        rustybuzz::Direction::RightToLeft
+    } else {
+        rustybuzz::Direction::LeftToRight
+    });
+    buffer.guess_segment_properties();
+
+    let output = rustybuzz::shape(&font, &[], buffer);
+
+    output
+        .glyph_infos()
+        .iter()
+        .zip(output.glyph_positions())
+        .map(|(info, position)| ShapedGlyph {
+            size: get_sizes.glyph(face, info.glyph_id),
+            cluster: info.cluster as usize,
+            advance: get_sizes.units_to_pixels(face, position.x_advance),
+            x_offset: get_sizes.units_to_pixels(face, position.x_offset),
+            y_offset: -get_sizes.units_to_pixels(face, position.y_offset),
+        })
+        .collect()
+}
+
+// One glyph per char with its kerned advance, for faces the shaper cannot
+// read.
+fn unshaped(text: &str, rtl: bool, get_sizes: &GlyphCache) -> Vec<ShapedGlyph> {
+    let mut chars = text.char_indices().peekable();
+    let mut glyphs = vec![];
+
+    while let Some((byte_index, c)) = chars.next() {
+        let size = get_sizes.get(c);
+        let kerning = chars
+            .peek()
+            .map_or(0, |&(_, next)| get_sizes.kerning(c, next));
+
+        glyphs.push(ShapedGlyph {
+            advance: size.advance + kerning,
+            size,
+            cluster: byte_index,
+            x_offset: 0,
+            y_offset: 0,
+        });
+    }
+
+    if rtl {
+        glyphs.reverse();
+    }
+    glyphs
+}
+
+// Width of every grapheme cluster of the text in its shaped glyphs. A glyph
+// counts for the grapheme cluster its shaping cluster starts in; a shaping
+// cluster spanning several of them, like a ligature, is shared out evenly so
+// the cursor can stop inside it.
+pub fn grapheme_widths(text: &str, glyphs: &[ShapedGlyph]) -> Vec<i64> {
+    let starts: Vec<usize> = text
+        .grapheme_indices(true)
+        .map(|(byte_index, _)| byte_index)
+        .collect();
+    let mut widths = vec![0; starts.len()];
+
+    let mut clusters: Vec<usize> = glyphs.iter().map(|glyph| glyph.cluster).collect();
+    clusters.sort_unstable();
+    clusters.dedup();
+
+    for glyph in glyphs {
+        let next = clusters
+            .get(clusters.partition_point(|&cluster| cluster <= glyph.cluster))
+            .map_or(text.len(), |&cluster| cluster);
+
+        let first = starts.partition_point(|&start| start <= glyph.cluster);
+        let first = first.saturating_sub(1);
+        let end = starts.partition_point(|&start| start < next).max(first + 1);
+        let count = (end - first) as i64;
+
+        for (index, width) in widths[first..end].iter_mut().enumerate() {
+            // The first cluster takes what does not divide evenly.
+            let remainder = if index == 0 { glyph.advance % count } else { 0 };
+            *width += glyph.advance / count + remainder;
+        }
+    }
+
+    widths
+}
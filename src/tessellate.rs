@@ -0,0 +1,296 @@
+// Turns glyph outlines into triangles. Contours inside an odd number of other
+// contours are holes; each hole is joined to its outer contour by a bridge
+// edge so the result is one simple polygon, which is then ear clipped.
+
+type Point = (f32, f32);
+
+const EPSILON: f32 = 1e-6;
+
+fn cross(a: Point, b: Point, c: Point) -> f32 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+fn signed_area(contour: &[Point]) -> f32 {
+    let mut area = 0.0;
+    for i in 0..contour.len() {
+        let (x1, y1) = contour[i];
+        let (x2, y2) = contour[(i + 1) % contour.len()];
+        area += x1 * y2 - x2 * y1;
+    }
+    area / 2.0
+}
+
+// Even-odd test, so doubled bridge edges cancel out.
+fn contains(contour: &[Point], point: Point) -> bool {
+    let mut inside = false;
+    let mut j = contour.len() - 1;
+
+    for i in 0..contour.len() {
+        let (xi, yi) = contour[i];
+        let (xj, yj) = contour[j];
+
+        if (yi > point.1) != (yj > point.1) && point.0 < (xj - xi) * (point.1 - yi) / (yj - yi) + xi
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+// Drops repeated points, including a closing point equal to the first.
+fn clean(contour: &[Point]) -> Vec<Point> {
+    let mut points: Vec<Point> = Vec::with_capacity(contour.len());
+
+    for &point in contour {
+        if points.last() != Some(&point) {
+            points.push(point);
+        }
+    }
+    while points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    points
+}
+
+// True when the segments cross at a point that is not an end of either.
+fn segments_cross(a: Point, b: Point, c: Point, d: Point) -> bool {
+    if a == c || a == d || b == c || b == d {
+        return false;
+    }
+
+    let d1 = cross(a, b, c);
+    let d2 = cross(a, b, d);
+    let d3 = cross(c, d, a);
+    let d4 = cross(c, d, b);
+
+    d1 * d2 < 0.0 && d3 * d4 < 0.0
+}
+
+fn crosses_contour(contour: &[Point], a: Point, b: Point) -> bool {
+    (0..contour.len()).any(|i| segments_cross(a, b, contour[i], contour[(i + 1) % contour.len()]))
+}
+
+// Whether `point` is seen from the inside of the corner at `index`.
+fn in_corner(polygon: &[Point], index: usize, point: Point) -> bool {
+    let len = polygon.len();
+    let prev = polygon[(index + len - 1) % len];
+    let corner = polygon[index];
+    let next = polygon[(index + 1) % len];
+
+    let left_of_prev = cross(prev, corner, point) >= 0.0;
+    let left_of_next = cross(corner, next, point) >= 0.0;
+
+    if cross(prev, corner, next) >= 0.0 {
+        left_of_prev && left_of_next
+    } else {
+        left_of_prev || left_of_next
+    }
+}
+
+// Splices `hole` into `polygon` through the nearest vertex that its rightmost
+// vertex can see.
+fn bridge(polygon: &mut Vec<Point>, hole: &[Point], pending: &[Vec<Point>]) {
+    let start = (0..hole.len())
+        .max_by(|&a, &b| hole[a].0.partial_cmp(&hole[b].0).unwrap())
+        .unwrap();
+    let from = hole[start];
+
+    let distance = |point: Point| (point.0 - from.0).powi(2) + (point.1 - from.1).powi(2);
+
+    let mut candidates: Vec<usize> = (0..polygon.len()).collect();
+    candidates.sort_by(|&a, &b| {
+        distance(polygon[a])
+            .partial_cmp(&distance(polygon[b]))
+            .unwrap()
+    });
+
+    let visible = candidates.iter().cloned().find(|&index| {
+        let to = polygon[index];
+        let middle = ((from.0 + to.0) / 2.0, (from.1 + to.1) / 2.0);
+
+        in_corner(polygon, index, from)
+            && !contains(hole, middle)
+            && !crosses_contour(polygon, from, to)
+            && !crosses_contour(hole, from, to)
+            && !pending.iter().any(|other| crosses_contour(other, from, to))
+    });
+
+    // Nothing is visible in broken outlines; the nearest vertex still gives
+    // something drawable.
+    let index = visible.unwrap_or(candidates[0]);
+
+    let mut spliced = Vec::with_capacity(polygon.len() + hole.len() + 2);
+    spliced.extend_from_slice(&polygon[..=index]);
+    spliced.extend_from_slice(&hole[start..]);
+    spliced.extend_from_slice(&hole[..=start]);
+    spliced.extend_from_slice(&polygon[index..]);
+    *polygon = spliced;
+}
+
+fn is_ear(points: &[Point], a: Point, b: Point, c: Point) -> bool {
+    !points.iter().any(|&point| {
+        point != a
+            && point != b
+            && point != c
+            && cross(a, b, point) >= 0.0
+            && cross(b, c, point) >= 0.0
+            && cross(c, a, point) >= 0.0
+    })
+}
+
+// Clips ears off a counter-clockwise polygon, pushing three points per
+// triangle.
+fn clip_ears(mut points: Vec<Point>, triangles: &mut Vec<Point>) {
+    let mut index = 0;
+    let mut stalled = 0;
+
+    while points.len() > 3 {
+        let len = points.len();
+        index %= len;
+
+        let a = points[(index + len - 1) % len];
+        let b = points[index];
+        let c = points[(index + 1) % len];
+        let turn = cross(a, b, c);
+
+        if turn.abs() <= EPSILON {
+            // Collinear points and the tips of bridges cover nothing.
+            points.remove(index);
+            stalled = 0;
+        } else if (turn > 0.0 && is_ear(&points, a, b, c)) || stalled > len {
+            triangles.extend_from_slice(&[a, b, c]);
+            points.remove(index);
+            stalled = 0;
+        } else {
+            index += 1;
+            stalled += 1;
+        }
+    }
+
+    if points.len() == 3 && cross(points[0], points[1], points[2]).abs() > EPSILON {
+        triangles.extend_from_slice(&points);
+    }
+}
+
+// Triangles covering the filled area of the outline, three points each.
+pub fn triangulate(contours: &[Vec<Point>]) -> Vec<Point> {
+    let contours: Vec<Vec<Point>> = contours
+        .iter()
+        .map(|contour| clean(contour))
+        .filter(|contour| contour.len() >= 3 && signed_area(contour).abs() > EPSILON)
+        .collect();
+
+    let depth: Vec<usize> = (0..contours.len())
+        .map(|i| {
+            (0..contours.len())
+                .filter(|&j| j != i && contains(&contours[j], contours[i][0]))
+                .count()
+        })
+        .collect();
+
+    let mut triangles = vec![];
+
+    for (outer_index, outer) in contours.iter().enumerate() {
+        if depth[outer_index] % 2 == 1 {
+            continue;
+        }
+
+        let mut polygon = outer.clone();
+        if signed_area(&polygon) < 0.0 {
+            polygon.reverse();
+        }
+
+        let mut holes: Vec<Vec<Point>> = (0..contours.len())
+            .filter(|&i| depth[i] == depth[outer_index] + 1 && contains(outer, contours[i][0]))
+            .map(|i| {
+                let mut hole = contours[i].clone();
+                if signed_area(&hole) > 0.0 {
+                    hole.reverse();
+                }
+                hole
+            })
+            .collect();
+
+        // Rightmost holes first, so later bridges can pass by earlier ones.
+        let max_x = |hole: &Vec<Point>| hole.iter().fold(f32::MIN, |max, point| max.max(point.0));
+        holes.sort_by(|a, b| max_x(b).partial_cmp(&max_x(a)).unwrap());
+
+        for index in 0..holes.len() {
+            bridge(&mut polygon, &holes[index], &holes[index + 1..]);
+        }
+
+        clip_ears(polygon, &mut triangles);
+    }
+
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area(triangles: &[Point]) -> f32 {
+        triangles
+            .chunks(3)
+            .map(|t| cross(t[0], t[1], t[2]).abs() / 2.0)
+            .sum()
+    }
+
+    fn square(x: f32, y: f32, size: f32) -> Vec<Point> {
+        vec![(x, y), (x + size, y), (x + size, y + size), (x, y + size)]
+    }
+
+    #[test]
+    fn square_is_two_triangles() {
+        let triangles = triangulate(&[square(0.0, 0.0, 2.0)]);
+
+        assert_eq!(triangles.len(), 6);
+        assert!((area(&triangles) - 4.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn concave_outlines_are_covered_exactly() {
+        let l_shape = vec![
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 3.0),
+            (0.0, 3.0),
+        ];
+        let triangles = triangulate(&[l_shape]);
+
+        assert_eq!(triangles.len(), 4 * 3);
+        assert!((area(&triangles) - 4.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn holes_are_left_empty() {
+        let mut hole = square(1.0, 1.0, 2.0);
+        hole.reverse();
+        let triangles = triangulate(&[square(0.0, 0.0, 4.0), hole]);
+
+        assert!((area(&triangles) - 12.0).abs() < 1e-4);
+        let center = (2.0, 2.0);
+        assert!(!triangles
+            .chunks(3)
+            .any(|t| contains(&[t[0], t[1], t[2]], center)));
+    }
+
+    #[test]
+    fn separate_contours_are_both_filled() {
+        let triangles = triangulate(&[square(0.0, 0.0, 1.0), square(3.0, 0.0, 1.0)]);
+
+        assert!((area(&triangles) - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn degenerate_contours_are_dropped() {
+        let line = vec![(0.0, 0.0), (1.0, 1.0), (2.0, 2.0)];
+        let closed = vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 0.0)];
+
+        assert!(triangulate(&[line, vec![(5.0, 5.0)]]).is_empty());
+        assert_eq!(triangulate(&[closed]).len(), 3);
+    }
+}
@@ -0,0 +1,133 @@
+use std::ops::Range;
+
+// The part of the wrapped rows that fits in the window.
+#[derive(Debug, Clone, Copy)]
+pub struct Viewport {
+    pub first_row: usize,
+    pub line_height: i64,
+    pub height: i64,
+}
+
+impl Viewport {
+    pub fn new(line_height: i64) -> Viewport {
+        Viewport {
+            first_row: 0,
+            line_height: line_height.max(1),
+            height: 0,
+        }
+    }
+
+    // Rows that are at least partly visible.
+    pub fn visible_row_count(&self) -> usize {
+        ((self.height + self.line_height - 1) / self.line_height).max(1) as usize
+    }
+
+    // Rows that are fully visible, what a page is.
+    pub fn page_row_count(&self) -> usize {
+        (self.height / self.line_height).max(1) as usize
+    }
+
+    pub fn visible(&self, row_count: usize) -> Range<usize> {
+        let end = (self.first_row + self.visible_row_count()).min(row_count);
+        self.first_row.min(end)..end
+    }
+
+    fn max_first_row(&self, row_count: usize) -> usize {
+        row_count.saturating_sub(self.page_row_count())
+    }
+
+    pub fn scroll_by(&mut self, rows: isize, row_count: usize) {
+        let first_row = (self.first_row as isize + rows).max(0) as usize;
+        self.first_row = first_row.min(self.max_first_row(row_count));
+    }
+
+    // Scrolls to a fraction of the document, 0.0 being the top.
+    pub fn scroll_to(&mut self, fraction: f32, row_count: usize) {
+        let first_row = (fraction.clamp(0.0, 1.0) * row_count as f32) as usize;
+        self.first_row = first_row.min(self.max_first_row(row_count));
+    }
+
+    // Scrolls as little as possible to make row `y` fully visible.
+    pub fn scroll_into_view(&mut self, y: usize, row_count: usize) {
+        let page = self.page_row_count();
+
+        if y < self.first_row {
+            self.first_row = y;
+        } else if y >= self.first_row + page {
+            self.first_row = y + 1 - page;
+        }
+        self.first_row = self.first_row.min(self.max_first_row(row_count));
+    }
+
+    // Start and end of the scrollbar thumb as fractions of the track.
+    pub fn scrollbar(&self, row_count: usize) -> (f32, f32) {
+        if row_count == 0 {
+            return (0.0, 1.0);
+        }
+
+        let start = self.first_row as f32 / row_count as f32;
+        let end = (self.first_row + self.page_row_count()) as f32 / row_count as f32;
+        (start, end.min(1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport(height: i64) -> Viewport {
+        let mut viewport = Viewport::new(10);
+        viewport.height = height;
+        viewport
+    }
+
+    #[test]
+    fn partly_visible_rows_are_drawn() {
+        let viewport = viewport(35);
+
+        assert_eq!(viewport.visible_row_count(), 4);
+        assert_eq!(viewport.page_row_count(), 3);
+        assert_eq!(viewport.visible(100), 0..4);
+        assert_eq!(viewport.visible(2), 0..2);
+    }
+
+    #[test]
+    fn scrolling_stops_at_the_last_page() {
+        let mut viewport = viewport(30);
+
+        viewport.scroll_by(5, 10);
+        assert_eq!(viewport.first_row, 5);
+        viewport.scroll_by(50, 10);
+        assert_eq!(viewport.first_row, 7);
+        viewport.scroll_by(-50, 10);
+        assert_eq!(viewport.first_row, 0);
+
+        viewport.scroll_to(0.5, 10);
+        assert_eq!(viewport.first_row, 5);
+        viewport.scroll_to(2.0, 10);
+        assert_eq!(viewport.first_row, 7);
+    }
+
+    #[test]
+    fn scroll_into_view_moves_the_least() {
+        let mut viewport = viewport(30);
+
+        viewport.scroll_into_view(5, 100);
+        assert_eq!(viewport.first_row, 3);
+        viewport.scroll_into_view(4, 100);
+        assert_eq!(viewport.first_row, 3);
+        viewport.scroll_into_view(1, 100);
+        assert_eq!(viewport.first_row, 1);
+    }
+
+    #[test]
+    fn thumb_covers_the_visible_fraction() {
+        let mut viewport = viewport(30);
+
+        assert_eq!(viewport.scrollbar(0), (0.0, 1.0));
+        assert_eq!(viewport.scrollbar(2), (0.0, 1.0));
+
+        viewport.scroll_by(3, 12);
+        assert_eq!(viewport.scrollbar(12), (0.25, 0.5));
+    }
+}